@@ -0,0 +1,50 @@
+// Reading and parsing large requests: 100 sequential 4 MiB POSTs to a
+// server on a loopback port, best of three runs. Run with
+//
+//     cargo bench --bench request_parsing
+//
+// The server logs a line per request, so redirect stdout to compare runs.
+
+use rake::{Response, SimpleHttpServer};
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const REQUESTS: usize = 100;
+const BODY_SIZE: usize = 4 << 20;
+
+fn main() {
+    // Let the OS pick a free port, then hand it to the server
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let addr = format!("127.0.0.1:{}", port);
+    let mut server = SimpleHttpServer::new();
+    server.post("/upload", |req, _| Response::text(&req.body.len().to_string()));
+    let listen = addr.clone();
+    thread::spawn(move || server.start(&listen));
+    while TcpStream::connect(&addr).is_err() {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut request = format!(
+        "POST /upload?a=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        BODY_SIZE
+    )
+    .into_bytes();
+    request.resize(request.len() + BODY_SIZE, b'x');
+
+    let mut best = Duration::MAX;
+    for _ in 0..3 {
+        let started = Instant::now();
+        for _ in 0..REQUESTS {
+            let mut stream = TcpStream::connect(&addr).unwrap();
+            stream.write_all(&request).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            assert!(response.starts_with(b"HTTP/1.1 200"), "upload failed");
+        }
+        best = best.min(started.elapsed());
+    }
+    eprintln!("{} x {} MiB POST: {:.3?}", REQUESTS, BODY_SIZE >> 20, best);
+}
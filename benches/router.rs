@@ -0,0 +1,107 @@
+// Route lookup through the trie against the linear scan it replaced, which
+// split every pattern on every request. Run with
+//
+//     cargo bench --bench router
+
+use rake::{Router, RouteMatch};
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const LOOKUPS: u32 = 200_000;
+
+// An app with `resources` REST resources, five routes each
+fn patterns(resources: usize) -> Vec<(&'static str, String)> {
+    let mut patterns = Vec::new();
+    for i in 0..resources {
+        patterns.push(("GET", format!("/api/res{}", i)));
+        patterns.push(("POST", format!("/api/res{}", i)));
+        patterns.push(("GET", format!("/api/res{}/<id:int>", i)));
+        patterns.push(("PUT", format!("/api/res{}/<id:int>", i)));
+        patterns.push(("GET", format!("/api/res{}/<id:int>/files/<path..>", i)));
+    }
+    patterns
+}
+
+// Old Router::lookup: try every route in registration order
+fn linear_lookup<'a>(patterns: &'a [(&str, String)], method: &str, path: &str) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|(m, pattern)| *m == method && match_route(pattern, path).is_some())
+        .map(|(_, pattern)| pattern.as_str())
+}
+
+// The matcher the linear scan ran for each route
+fn match_route(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let pat_parts: Vec<_> = pattern.trim_matches('/').split('/').collect();
+    let path_parts: Vec<_> = path.trim_matches('/').split('/').collect();
+    let rest_param = pat_parts
+        .last()
+        .and_then(|last| last.strip_prefix('<')?.strip_suffix("..>"));
+    if let Some(name) = rest_param {
+        let fixed = pat_parts.len() - 1;
+        if path_parts.len() <= fixed || path_parts[fixed..].concat().is_empty() {
+            return None;
+        }
+        params.insert(name.to_string(), path_parts[fixed..].join("/"));
+    } else if pat_parts.len() != path_parts.len() {
+        return None;
+    }
+    for (pat, val) in pat_parts.iter().zip(path_parts.iter()) {
+        if pat.ends_with("..>") {
+            break;
+        }
+        if pat.starts_with('<') && pat.ends_with('>') {
+            let param = &pat[1..pat.len() - 1];
+            let (name, kind) = param.split_once(':').unwrap_or((param, "str"));
+            if kind == "int" && val.parse::<i64>().is_err() {
+                return None;
+            }
+            params.insert(name.to_string(), val.to_string());
+        } else if pat != val {
+            return None;
+        }
+    }
+    Some(params)
+}
+
+fn time(mut lookup: impl FnMut()) -> Duration {
+    let started = Instant::now();
+    for _ in 0..LOOKUPS {
+        lookup();
+    }
+    started.elapsed() / LOOKUPS
+}
+
+fn main() {
+    for resources in [2, 20, 100] {
+        let patterns = patterns(resources);
+        let mut router = Router::new();
+        for (method, pattern) in &patterns {
+            router.add_route(method, pattern, |_, _| "");
+        }
+        let last = resources - 1;
+        let paths = [
+            ("GET", format!("/api/res{}", last)),
+            ("GET", format!("/api/res{}/42", last)),
+            ("GET", format!("/api/res{}/42/files/a/b.txt", last)),
+            ("GET", "/nowhere/at/all".to_string()),
+        ];
+        println!("{} routes", patterns.len());
+        for (method, path) in &paths {
+            // Both must agree on what matches
+            let found = matches!(router.lookup(method, path), RouteMatch::Found(..));
+            assert_eq!(found, linear_lookup(&patterns, method, path).is_some(), "{}", path);
+
+            let trie = time(|| {
+                black_box(router.lookup(black_box(method), black_box(path)));
+            });
+            let linear = time(|| {
+                black_box(linear_lookup(&patterns, black_box(method), black_box(path)));
+            });
+            println!("  {:<32} trie {:>8.2?}  linear {:>8.2?}", path, trie, linear);
+        }
+    }
+}
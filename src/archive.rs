@@ -0,0 +1,265 @@
+use crate::compress::{gunzip, inflate};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy)]
+enum Storage {
+    Stored,
+    Deflated,
+}
+
+#[derive(Clone)]
+struct Entry {
+    offset: u64,
+    size: u64,
+    storage: Storage,
+    modified: Option<SystemTime>,
+}
+
+enum Source {
+    // Entries are read from the file on demand
+    File(Mutex<File>),
+    // .tar.gz has to be decompressed up front to be seekable
+    Memory(Vec<u8>),
+}
+
+// A .zip, .tar or .tar.gz opened once with its file index kept in memory
+pub(crate) struct Archive {
+    source: Source,
+    entries: HashMap<String, Entry>,
+}
+
+pub(crate) struct ArchiveFile {
+    pub(crate) contents: Vec<u8>,
+    pub(crate) modified: Option<SystemTime>,
+}
+
+impl Archive {
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let name = path.to_string_lossy().to_ascii_lowercase();
+        let mut file = File::open(path)?;
+        let (source, entries) = if name.ends_with(".zip") {
+            let entries = index_zip(&mut file)?;
+            (Source::File(Mutex::new(file)), entries)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            let mut compressed = Vec::new();
+            file.read_to_end(&mut compressed)?;
+            let data = gunzip(&compressed).ok_or_else(|| invalid("corrupt gzip stream"))?;
+            let entries = index_tar(&mut io::Cursor::new(&data))?;
+            (Source::Memory(data), entries)
+        } else if name.ends_with(".tar") {
+            let entries = index_tar(&mut file)?;
+            (Source::File(Mutex::new(file)), entries)
+        } else {
+            return Err(invalid("expected a .zip, .tar or .tar.gz archive"));
+        };
+        Ok(Self { source, entries })
+    }
+
+    // `name` is relative to the archive root, without a leading slash
+    pub(crate) fn read(&self, name: &str) -> Option<ArchiveFile> {
+        let entry = self.entries.get(name)?;
+        let raw = match &self.source {
+            Source::File(file) => {
+                let mut file = file.lock().unwrap();
+                file.seek(SeekFrom::Start(entry.offset)).ok()?;
+                let mut raw = vec![0; entry.size as usize];
+                file.read_exact(&mut raw).ok()?;
+                raw
+            }
+            Source::Memory(data) => {
+                let start = entry.offset as usize;
+                data.get(start..start + entry.size as usize)?.to_vec()
+            }
+        };
+        let contents = match entry.storage {
+            Storage::Stored => raw,
+            Storage::Deflated => inflate(&raw)?,
+        };
+        Some(ArchiveFile {
+            contents,
+            modified: entry.modified,
+        })
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn le16(b: &[u8], at: usize) -> u64 {
+    u16::from_le_bytes([b[at], b[at + 1]]) as u64
+}
+
+fn le32(b: &[u8], at: usize) -> u64 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]]) as u64
+}
+
+// Walk the central directory (zip64 is not supported)
+fn index_zip(file: &mut File) -> io::Result<HashMap<String, Entry>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    // End-of-central-directory record: 22 bytes plus up to 64 KiB of comment
+    let tail_len = len.min(22 + 65535);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    let eocd = tail
+        .windows(4)
+        .rposition(|w| w == b"PK\x05\x06")
+        .filter(|&pos| pos + 22 <= tail.len())
+        .ok_or_else(|| invalid("not a zip archive"))?;
+    let count = le16(&tail, eocd + 10);
+    let cd_size = le32(&tail, eocd + 12);
+    let cd_offset = le32(&tail, eocd + 16);
+
+    file.seek(SeekFrom::Start(cd_offset))?;
+    let mut cd = vec![0; cd_size as usize];
+    file.read_exact(&mut cd)?;
+
+    let mut entries = HashMap::new();
+    let mut pos = 0;
+    for _ in 0..count {
+        if cd.get(pos..pos + 4) != Some(b"PK\x01\x02") || pos + 46 > cd.len() {
+            return Err(invalid("corrupt zip central directory"));
+        }
+        let method = le16(&cd, pos + 10);
+        let (time, date) = (le16(&cd, pos + 12), le16(&cd, pos + 14));
+        let compressed_size = le32(&cd, pos + 20);
+        let name_len = le16(&cd, pos + 28) as usize;
+        let extra_len = le16(&cd, pos + 30) as usize;
+        let comment_len = le16(&cd, pos + 32) as usize;
+        let local_offset = le32(&cd, pos + 42);
+        let name = cd
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or_else(|| invalid("corrupt zip central directory"))?;
+        let name = String::from_utf8_lossy(name).to_string();
+        pos += 46 + name_len + extra_len + comment_len;
+
+        let storage = match method {
+            0 => Storage::Stored,
+            8 => Storage::Deflated,
+            _ => continue,
+        };
+        if name.ends_with('/') {
+            continue;
+        }
+
+        // The local header's name/extra lengths may differ from the central copy
+        let mut local = [0u8; 30];
+        file.seek(SeekFrom::Start(local_offset))?;
+        file.read_exact(&mut local)?;
+        let offset = local_offset + 30 + le16(&local, 26) + le16(&local, 28);
+
+        entries.insert(
+            name.trim_start_matches("./").to_string(),
+            Entry {
+                offset,
+                size: compressed_size,
+                storage,
+                modified: dos_time(date, time),
+            },
+        );
+    }
+    Ok(entries)
+}
+
+fn dos_time(date: u64, time: u64) -> Option<SystemTime> {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf) as i64;
+    let day = (date & 0x1f) as i64;
+    if month == 0 || day == 0 {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86400
+        + ((time >> 11) * 3600 + ((time >> 5) & 0x3f) * 60 + (time & 0x1f) * 2) as i64;
+    Some(UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64))
+}
+
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn octal(field: &[u8]) -> u64 {
+    field
+        .iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|&&b| (b'0'..=b'7').contains(&b))
+        .fold(0, |acc, &b| acc * 8 + (b - b'0') as u64)
+}
+
+fn cstr(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+// ustar with GNU long names and pax `path` records
+fn index_tar<R: Read + Seek>(reader: &mut R) -> io::Result<HashMap<String, Entry>> {
+    let mut entries = HashMap::new();
+    let mut long_name: Option<String> = None;
+    let mut header = [0u8; 512];
+    let mut offset = 0u64;
+
+    loop {
+        reader.seek(SeekFrom::Start(offset))?;
+        if reader.read_exact(&mut header).is_err() || header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = octal(&header[124..136]);
+        let data_offset = offset + 512;
+        offset = data_offset + size.div_ceil(512) * 512;
+
+        match header[156] {
+            b'L' | b'x' => {
+                let mut data = vec![0; size as usize];
+                reader.read_exact(&mut data)?;
+                long_name = if header[156] == b'L' {
+                    Some(cstr(&data))
+                } else {
+                    pax_path(&data)
+                };
+            }
+            b'0' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let prefix = cstr(&header[345..500]);
+                    let name = cstr(&header[0..100]);
+                    if prefix.is_empty() {
+                        name
+                    } else {
+                        format!("{}/{}", prefix, name)
+                    }
+                });
+                let mtime = octal(&header[136..148]);
+                entries.insert(
+                    name.trim_start_matches("./").to_string(),
+                    Entry {
+                        offset: data_offset,
+                        size,
+                        storage: Storage::Stored,
+                        modified: Some(UNIX_EPOCH + Duration::from_secs(mtime)),
+                    },
+                );
+            }
+            _ => long_name = None,
+        }
+    }
+    Ok(entries)
+}
+
+// Records look like "<len> path=<value>\n"
+fn pax_path(data: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|line| line.split_once(' ').map(|(_, kv)| kv))
+        .find_map(|kv| kv.strip_prefix("path=").map(|p| p.to_string()))
+}
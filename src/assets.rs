@@ -0,0 +1,160 @@
+// Content-hashed asset URLs for long-lived caching. At startup every file in
+// the static dir gets a fingerprinted alias ("css/app.css" is also served
+// as "css/app.1a2b3c4d.css" with an immutable Cache-Control), and
+// req.asset() maps the plain name to that alias so pages always link the
+// current build.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+// Long enough that "immutable" can be taken literally
+pub(crate) const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+#[derive(Default)]
+struct Manifest {
+    // "css/app.css" -> "css/app.1a2b3c4d.css"
+    hashed: HashMap<String, String>,
+    // and back
+    original: HashMap<String, String>,
+}
+
+// One server's manifest, filled in when it starts. Its clones share it and
+// every request carries it, for req.asset().
+#[derive(Clone, Default)]
+pub(crate) struct Assets(Arc<RwLock<Manifest>>);
+
+impl Assets {
+    // URL for a static file, fingerprinted when fingerprinting is enabled
+    // and the file exists, e.g. "app.css" -> "/app.1a2b3c4d.css"
+    pub(crate) fn asset(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        let manifest = self.0.read().unwrap();
+        format!("/{}", manifest.hashed.get(path).map(|s| s.as_str()).unwrap_or(path))
+    }
+
+    pub(crate) fn expand(&self, template: &str) -> String {
+        expand_assets(template, |path| self.asset(path))
+    }
+
+    // The file behind a fingerprinted request path, relative to the static dir
+    pub(crate) fn original(&self, request_path: &str) -> Option<String> {
+        let manifest = self.0.read().unwrap();
+        manifest
+            .original
+            .get(request_path.trim_start_matches('/'))
+            .cloned()
+    }
+
+    // Hash every file under `dir` and replace the manifest. Returns the count.
+    pub(crate) fn fingerprint_dir(&self, dir: &Path) -> io::Result<usize> {
+        let mut manifest = Manifest::default();
+        walk(dir, dir, &mut manifest)?;
+        let count = manifest.hashed.len();
+        *self.0.write().unwrap() = manifest;
+        Ok(count)
+    }
+}
+
+// Replace `{{ asset("app.css") }}` placeholders in a template using `asset`
+fn expand_assets<F>(template: &str, asset: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let inner = after[2..end].trim();
+        match inner
+            .strip_prefix("asset(")
+            .and_then(|s| s.strip_suffix(')'))
+            .map(|s| s.trim().trim_matches('"'))
+        {
+            Some(path) => out.push_str(&asset(path)),
+            None => out.push_str(&after[..end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn walk(root: &Path, dir: &Path, manifest: &mut Manifest) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            walk(root, &path, manifest)?;
+            continue;
+        }
+        // Precompressed siblings are picked up through their source file
+        if matches!(path.extension().and_then(|e| e.to_str()), Some("gz" | "br"))
+            && path.with_extension("").is_file()
+        {
+            continue;
+        }
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let hashed = hashed_name(&relative, &fs::read(&path)?);
+        manifest.original.insert(hashed.clone(), relative.clone());
+        manifest.hashed.insert(relative, hashed);
+    }
+    Ok(())
+}
+
+// "css/app.css" -> "css/app.<8 hex digits>.css"
+fn hashed_name(relative: &str, contents: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in contents {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    let digest = format!("{:08x}", (hash >> 32) as u32 ^ hash as u32);
+    let (dir, file) = match relative.rsplit_once('/') {
+        Some((dir, file)) => (format!("{}/", dir), file),
+        None => (String::new(), relative),
+    };
+    match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}{}.{}.{}", dir, stem, digest, ext),
+        _ => format!("{}{}.{}", dir, file, digest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifests_are_per_server() {
+        let dir = std::env::temp_dir().join(format!("rake-assets-{}", std::process::id()));
+        fs::create_dir_all(dir.join("css")).unwrap();
+        fs::write(dir.join("css/app.css"), "body {}").unwrap();
+
+        let assets = Assets::default();
+        let other = Assets::default();
+        assert_eq!(assets.fingerprint_dir(&dir).unwrap(), 1);
+        let hashed = assets.asset("/css/app.css");
+        assert_ne!(hashed, "/css/app.css");
+        assert_eq!(assets.original(&hashed).as_deref(), Some("css/app.css"));
+        assert_eq!(
+            assets.expand(r#"<link href="{{ asset("css/app.css") }}">"#),
+            format!(r#"<link href="{}">"#, hashed)
+        );
+        assert_eq!(other.asset("css/app.css"), "/css/app.css");
+        assert_eq!(other.original(&hashed), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,40 @@
+// Standard base64 (RFC 4648 section 4), as used by HTTP Basic auth, and
+// the URL-safe alphabet (section 5) of HTTP2-Settings
+
+// None on characters outside the alphabet or a length that can't be valid.
+// Padding is optional.
+pub(crate) fn decode(input: &str) -> Option<Vec<u8>> {
+    decode_with(input, b'+', b'/')
+}
+
+pub(crate) fn decode_url(input: &str) -> Option<Vec<u8>> {
+    decode_with(input, b'-', b'_')
+}
+
+fn decode_with(input: &str, c62: u8, c63: u8) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=').as_bytes();
+    if input.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &c in input {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            _ if c == c62 => 62,
+            _ if c == c63 => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
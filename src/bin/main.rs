@@ -1,4 +1,4 @@
-use rake::{SimpleHttpServer, Request, Response, TemplateEngine};
+use rake::{Params, SimpleHttpServer, Request, Response, TemplateEngine};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::fs;
@@ -34,7 +34,7 @@ impl TemplateEngine for SimpleTemplateEngine {
 }
 
 // Handler for /hello/<name>
-fn hello_handler(_req: &Request, params: &HashMap<String, String>) -> Response {
+fn hello_handler(_req: &Request, params: &Params) -> Response {
     let default = "world".to_string();
     let name = params.get("name").unwrap_or(&default);
     let body = format!("Hello, {}!", name);
@@ -42,12 +42,12 @@ fn hello_handler(_req: &Request, params: &HashMap<String, String>) -> Response {
 }
 
 // Handler for /echo (POST)
-fn echo_handler(req: &Request, _params: &HashMap<String, String>) -> Response {
+fn echo_handler(req: &Request, _params: &Params) -> Response {
     Response::new(200, req.body.clone(), "text/plain")
 }
 
 // Handler for /hello-template-string/<name>
-fn template_string_hello_handler(_req: &Request, params: &HashMap<String, String>) -> Response {
+fn template_string_hello_handler(req: &Request, params: &Params) -> Response {
     let engine = SimpleTemplateEngine::new();
 
     // Both {{ name }} and {{name}} will work
@@ -57,27 +57,26 @@ fn template_string_hello_handler(_req: &Request, params: &HashMap<String, String
     let mut context = HashMap::new();
     context.insert("name".to_string(), name);
 
-    let rendered = engine.render_str(template, &context);
+    // {{ url_for("hello", name=bob) }} links a named route and
+    // {{ asset("app.css") }} the fingerprinted file
+    let rendered = req.expand_assets(&req.expand_urls(&engine.render_str(template, &context)));
     Response::new(200, rendered.into_bytes(), "text/html")
 }
 
 // Handler for /hello-template-file/<name>
-fn template_file_hello_handler(_req: &Request, params: &HashMap<String, String>) -> Response {
+fn template_file_hello_handler(req: &Request, params: &Params) -> Response {
     let mut context = HashMap::new();
     let name = params.get("name").cloned().unwrap_or_else(|| "World".to_string());
     context.insert("name".to_string(), name);
 
     let engine = SimpleTemplateEngine::new();
-    let rendered = engine.render("public/hello.html", &context);
+    let rendered = req.expand_assets(&req.expand_urls(&engine.render("public/hello.html", &context)));
 
     Response::new(200, rendered.into_bytes(), "text/html")
 }
 
-fn template_file_about_handler(_req: &Request, _params: &HashMap<String, String>) -> Response {
-    match fs::read("public/about.html") {
-        Ok(contents) => Response::new(200, contents, "text/html"),
-        Err(_) => Response::new(404, b"File not found".to_vec(), "text/plain"),
-    }
+fn template_file_about_handler(_req: &Request, _params: &Params) -> Response {
+    Response::file("public/about.html")
 }
 
 fn main() {
@@ -85,18 +84,18 @@ fn main() {
 
     server.set_template_engine(Arc::new(SimpleTemplateEngine::new()));
 
-    server.route("GET", "/hello/<name>", hello_handler);
-    server.route("POST", "/echo", echo_handler);
+    server.route_named("hello", "GET", "/hello/<name>", hello_handler);
+    server.post("/echo", echo_handler);
 
     // Serve static files from ./static directory
     server.static_dir("./public");
     
     // server.static_dir("./static/public");
 
-    server.route("GET", "/about/", template_file_about_handler);
+    server.get("/about/", template_file_about_handler);
 
-    server.route("GET", "/hello-template-string/<name>", template_string_hello_handler);
-    server.route("GET", "/hello-template-file/<name>", template_file_hello_handler);
+    server.get("/hello-template-string/<name>", template_string_hello_handler);
+    server.get("/hello-template-file/<name>", template_file_hello_handler);
 
     server.start("127.0.0.1:7878");
 }
\ No newline at end of file
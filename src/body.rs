@@ -0,0 +1,190 @@
+// Request bodies read straight from the connection, for routes registered
+// with .stream_body()
+
+use crate::chunked;
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+// Longest chunk-size or trailer line accepted
+const MAX_LINE: usize = 4096;
+
+// How the end of the body is found
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Framing {
+    Length(usize),
+    Chunked,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    // Bytes left of a Content-Length body
+    Length(usize),
+    ChunkSize,
+    // Bytes left of the current chunk
+    ChunkData(usize),
+    ChunkEnd,
+    Trailers,
+    Done,
+}
+
+// The body of a request as an io::Read, e.g. to io::copy an upload to a
+// file with bounded memory. Chunked framing is removed; trailers can be
+// had from trailers() once the body has been read to the end. Part of the
+// body that isn't read is discarded.
+pub struct BodyReader {
+    // Bytes that arrived along with the request head, then each refill
+    buffer: Vec<u8>,
+    pos: usize,
+    stream: TcpStream,
+    timeout: Option<Duration>,
+    // Body bytes still allowed before reads fail
+    allowance: Option<usize>,
+    expect_continue: bool,
+    state: State,
+    trailers: HashMap<String, String>,
+}
+
+impl BodyReader {
+    pub(crate) fn new(
+        framing: Framing,
+        received: Vec<u8>,
+        stream: TcpStream,
+        timeout: Option<Duration>,
+        max_size: Option<usize>,
+        expect_continue: bool,
+    ) -> Self {
+        Self {
+            buffer: received,
+            pos: 0,
+            stream,
+            timeout,
+            allowance: max_size,
+            expect_continue,
+            state: match framing {
+                Framing::Length(len) => State::Length(len),
+                Framing::Chunked => State::ChunkSize,
+            },
+            trailers: HashMap::new(),
+        }
+    }
+
+    // Fields sent after a chunked body, as in Request::trailers. Empty until
+    // read() has returned 0.
+    pub fn trailers(&self) -> &HashMap<String, String> {
+        &self.trailers
+    }
+
+    // Make sure unread bytes are buffered; false at end of stream
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.pos < self.buffer.len() {
+            return Ok(true);
+        }
+        // The client waits for this before sending the body
+        if std::mem::take(&mut self.expect_continue) {
+            self.stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        self.stream.set_read_timeout(self.timeout)?;
+        self.buffer.resize(8192, 0);
+        let n = self.stream.read(&mut self.buffer)?;
+        self.buffer.truncate(n);
+        self.pos = 0;
+        Ok(n > 0)
+    }
+
+    // Copy up to `limit` body bytes into `buf`
+    fn read_data(&mut self, buf: &mut [u8], limit: usize) -> io::Result<usize> {
+        if self.allowance == Some(0) {
+            return Err(invalid("request body is larger than the server accepts"));
+        }
+        if !self.fill()? {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "request body ended early"));
+        }
+        let n = buf
+            .len()
+            .min(limit)
+            .min(self.allowance.unwrap_or(usize::MAX))
+            .min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        if let Some(allowance) = &mut self.allowance {
+            *allowance -= n;
+        }
+        Ok(n)
+    }
+
+    // Line ending in CRLF, without the CRLF
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        loop {
+            if !self.fill()? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "request body ended early"));
+            }
+            let byte = self.buffer[self.pos];
+            self.pos += 1;
+            if byte == b'\n' && line.last() == Some(&b'\r') {
+                line.pop();
+                return Ok(line);
+            }
+            if line.len() >= MAX_LINE {
+                return Err(invalid("chunk line too long"));
+            }
+            line.push(byte);
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.state {
+                State::Length(0) | State::Done => return Ok(0),
+                State::Length(left) => {
+                    let n = self.read_data(buf, left)?;
+                    self.state = State::Length(left - n);
+                    return Ok(n);
+                }
+                State::ChunkSize => {
+                    let line = self.read_line()?;
+                    let size = line.split(|&b| b == b';').next().unwrap_or(b"");
+                    let size = std::str::from_utf8(size)
+                        .ok()
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit()))
+                        .and_then(|s| usize::from_str_radix(s, 16).ok())
+                        .ok_or_else(|| invalid("invalid chunk size"))?;
+                    self.state = if size == 0 { State::Trailers } else { State::ChunkData(size) };
+                }
+                State::ChunkData(left) => {
+                    let n = self.read_data(buf, left)?;
+                    self.state = if n == left { State::ChunkEnd } else { State::ChunkData(left - n) };
+                    return Ok(n);
+                }
+                State::ChunkEnd => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(invalid("chunk data longer than its size"));
+                    }
+                    self.state = State::ChunkSize;
+                }
+                State::Trailers => {
+                    let line = self.read_line()?;
+                    if line.is_empty() {
+                        self.state = State::Done;
+                    } else {
+                        chunked::add_trailer(&line, &mut self.trailers)
+                            .map_err(|_| invalid("invalid trailer field"))?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,128 @@
+// Decoding of `Transfer-Encoding: chunked` request bodies
+
+use std::collections::HashMap;
+
+// Fields that must not appear in a trailer (RFC 9110 section 6.5.1): they
+// control framing, routing or authentication and were already acted on
+const FORBIDDEN_TRAILERS: &[&str] = &[
+    "authorization",
+    "cache-control",
+    "content-encoding",
+    "content-length",
+    "content-range",
+    "content-type",
+    "expect",
+    "host",
+    "max-forwards",
+    "set-cookie",
+    "te",
+    "trailer",
+    "transfer-encoding",
+];
+
+pub(crate) struct Decoded {
+    pub(crate) body: Vec<u8>,
+    pub(crate) trailers: HashMap<String, String>,
+    // Bytes of `data` taken up by the chunked message, trailers included
+    pub(crate) consumed: usize,
+}
+
+#[derive(Debug)]
+pub(crate) enum ChunkError {
+    // The message stops before the terminating empty line
+    Incomplete,
+    Invalid,
+}
+
+// Decode a chunked body from the start of `data`: sizes in hex with optional
+// `;ext` parameters, then a zero-size chunk, optional trailer fields and an
+// empty line
+pub(crate) fn decode(data: &[u8]) -> Result<Decoded, ChunkError> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+    walk_chunks(data, &mut pos, |chunk| body.extend_from_slice(chunk))?;
+
+    let mut trailers = HashMap::new();
+    loop {
+        let line = read_line(data, &mut pos)?;
+        if line.is_empty() {
+            break;
+        }
+        add_trailer(line, &mut trailers)?;
+    }
+
+    Ok(Decoded {
+        body,
+        trailers,
+        consumed: pos,
+    })
+}
+
+// Add one trailer field line to `trailers`, joining repeats with ", ".
+// Fields that may not be trailers are dropped.
+pub(crate) fn add_trailer(line: &[u8], trailers: &mut HashMap<String, String>) -> Result<(), ChunkError> {
+    let line = String::from_utf8_lossy(line);
+    let Some((key, value)) = line.split_once(':') else {
+        return Err(ChunkError::Invalid);
+    };
+    let key = key.trim().to_ascii_lowercase();
+    if key.is_empty() || FORBIDDEN_TRAILERS.contains(&key.as_str()) {
+        return Ok(());
+    }
+    let value = value.trim().to_string();
+    trailers
+        .entry(key)
+        .and_modify(|existing: &mut String| {
+            existing.push_str(", ");
+            existing.push_str(&value);
+        })
+        .or_insert(value);
+    Ok(())
+}
+
+// Length of the complete chunked message at the start of `data`, found
+// without copying the body, to tell whether more has to be read first
+pub(crate) fn message_len(data: &[u8]) -> Result<usize, ChunkError> {
+    let mut pos = 0;
+    walk_chunks(data, &mut pos, |_| {})?;
+    while !read_line(data, &mut pos)?.is_empty() {}
+    Ok(pos)
+}
+
+// Pass each chunk's data to `on_chunk`, leaving `pos` just past the
+// zero-size chunk's line
+fn walk_chunks(data: &[u8], pos: &mut usize, mut on_chunk: impl FnMut(&[u8])) -> Result<(), ChunkError> {
+    loop {
+        let line = read_line(data, pos)?;
+        let size_str = line.split(|&b| b == b';').next().unwrap_or(b"");
+        let size_str = std::str::from_utf8(size_str).map_err(|_| ChunkError::Invalid)?.trim();
+        if size_str.is_empty() || !size_str.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ChunkError::Invalid);
+        }
+        let size = usize::from_str_radix(size_str, 16).map_err(|_| ChunkError::Invalid)?;
+        if size == 0 {
+            return Ok(());
+        }
+        let end = pos.checked_add(size).ok_or(ChunkError::Invalid)?;
+        let next = end.checked_add(2).ok_or(ChunkError::Invalid)?;
+        if data.len() < next {
+            return Err(ChunkError::Incomplete);
+        }
+        if &data[end..next] != b"\r\n" {
+            return Err(ChunkError::Invalid);
+        }
+        on_chunk(&data[*pos..end]);
+        *pos = next;
+    }
+}
+
+// Line ending in CRLF starting at `pos`, advancing past it
+fn read_line<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a [u8], ChunkError> {
+    let rest = &data[*pos..];
+    let end = rest
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .ok_or(ChunkError::Incomplete)?;
+    *pos += end + 2;
+    Ok(&rest[..end])
+}
@@ -0,0 +1,443 @@
+// Minimal gzip (RFC 1951/1952) encoder: LZ77 matching with the fixed Huffman
+// code. Not as tight as zlib, but dependency-free and fine for text assets.
+
+use crate::request::Request;
+use crate::response::Response;
+use crate::static_files::{accepts_encoding, is_compressible_type, Compressor, MIN_COMPRESS_SIZE};
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { out: Vec::new(), acc: 0, bits: 0 }
+    }
+
+    // Write `count` bits of `value`, least significant first
+    fn write(&mut self, value: u32, count: u32) {
+        self.acc |= value << self.bits;
+        self.bits += count;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting from their most significant bit
+    fn write_code(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(w: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => w.write_code(0x30 + symbol, 8),
+        144..=255 => w.write_code(0x190 + symbol - 144, 9),
+        256..=279 => w.write_code(symbol - 256, 7),
+        _ => w.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let li = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+    write_literal(w, 257 + li as u32);
+    w.write((len - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li] as u32);
+
+    let di = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    w.write_code(di as u32, 5);
+    w.write((dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+fn hash3(data: &[u8], i: usize) -> usize {
+    let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+// Raw DEFLATE stream: a single final block with fixed Huffman codes
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write(1, 1); // BFINAL
+    w.write(1, 2); // BTYPE = fixed Huffman
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let mut i = 0;
+
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, pos: usize| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash3(data, pos);
+            prev[pos % WINDOW] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash3(data, i)];
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut w, best_len, best_dist);
+            for pos in i..i + best_len {
+                insert(&mut head, &mut prev, pos);
+            }
+            i += best_len;
+        } else {
+            write_literal(&mut w, data[i] as u32);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+
+    write_literal(&mut w, 256); // end of block
+    w.finish()
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, CM=deflate, no flags, no mtime, no extra flags, OS=unknown
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend(deflate(data));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+// Brotli at its highest quality, which is only affordable because static
+// files are compressed once ahead of time
+#[cfg(feature = "brotli")]
+pub(crate) fn brotli(data: &[u8]) -> Vec<u8> {
+    brotli_at(data, 11)
+}
+
+#[cfg(feature = "brotli")]
+fn brotli_at(data: &[u8], quality: i32) -> Vec<u8> {
+    let mut out = Vec::new();
+    let params = brotli::enc::BrotliEncoderParams {
+        quality,
+        ..Default::default()
+    };
+    // Writing to a Vec can't fail
+    brotli::BrotliCompress(&mut &data[..], &mut out, &params).expect("in-memory brotli compression");
+    out
+}
+
+// A quality cheap enough to pay on every response
+#[cfg(feature = "brotli")]
+fn brotli_fast(data: &[u8]) -> Vec<u8> {
+    brotli_at(data, 5)
+}
+
+// Encodings tried for a response, best first
+#[cfg(feature = "brotli")]
+const RUNTIME_ENCODINGS: &[(&str, Compressor)] = &[("br", brotli_fast), ("gzip", gzip)];
+#[cfg(not(feature = "brotli"))]
+const RUNTIME_ENCODINGS: &[(&str, Compressor)] = &[("gzip", gzip)];
+
+// Encode a compressible response with the best encoding the client accepts.
+// Streamed, partial, already encoded and no-transform responses are left
+// alone, as is anything that doesn't shrink. A strong ETag becomes weak,
+// since the bytes now differ from the identity representation.
+pub(crate) fn compress_response(req: &Request, mut response: Response) -> Response {
+    let accept_encoding = req.header("accept-encoding").map_or("", |s| s.as_str());
+    let mime = response.content_type.split(';').next().unwrap_or("").trim();
+    if !matches!(response.status_code, 200..=299)
+        || matches!(response.status_code, 204 | 206)
+        || response.is_streamed()
+        || (response.body.len() as u64) < MIN_COMPRESS_SIZE
+        || !is_compressible_type(mime)
+        || response.header("content-encoding").is_some()
+        || response.header("content-range").is_some()
+        || response
+            .header("cache-control")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("no-transform"))
+    {
+        return response;
+    }
+    // Whatever this client gets, the answer depends on Accept-Encoding
+    response.headers.append("Vary", "Accept-Encoding");
+    let Some((encoding, compress)) = RUNTIME_ENCODINGS
+        .iter()
+        .find(|(encoding, _)| accepts_encoding(accept_encoding, encoding))
+    else {
+        return response;
+    };
+    let compressed = compress(&response.body);
+    if compressed.len() >= response.body.len() {
+        return response;
+    }
+    response.body = compressed;
+    response.headers.insert("Content-Encoding", *encoding);
+    if let Some(etag) = response.header("etag").cloned()
+        && !etag.starts_with("W/")
+    {
+        response.headers.insert("ETag", format!("W/{}", etag));
+    }
+    response
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u64,
+    bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0, acc: 0, bits: 0 }
+    }
+
+    fn need(&mut self, count: u32) -> Option<()> {
+        while self.bits < count {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            self.acc |= (byte as u64) << self.bits;
+            self.bits += 8;
+        }
+        Some(())
+    }
+
+    fn read(&mut self, count: u32) -> Option<u32> {
+        if count == 0 {
+            return Some(0);
+        }
+        self.need(count)?;
+        let value = (self.acc & ((1u64 << count) - 1)) as u32;
+        self.acc >>= count;
+        self.bits -= count;
+        Some(value)
+    }
+
+    // Drop the partial byte before a stored block
+    fn align(&mut self) {
+        let skip = self.bits % 8;
+        self.acc >>= skip;
+        self.bits -= skip;
+    }
+}
+
+// Canonical Huffman decoding table: symbols sorted by code, plus counts per length
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Option<Self> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Some(Self { counts, symbols })
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Option<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= r.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5u8; 30]).unwrap())
+}
+
+fn dynamic_tables(r: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let hlit = r.read(5)? as usize + 257;
+    let hdist = r.read(5)? as usize + 1;
+    let hclen = r.read(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in ORDER.iter().take(hclen) {
+        code_lengths[i] = r.read(3)? as u8;
+    }
+    let code_table = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; hlit + hdist];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_table.decode(r)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.get(i.checked_sub(1)?)?, 3 + r.read(2)? as usize),
+            17 => (0, 3 + r.read(3)? as usize),
+            18 => (0, 11 + r.read(7)? as usize),
+            _ => return None,
+        };
+        if i + repeat > lengths.len() {
+            return None;
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    Some((Huffman::new(&lengths[..hlit])?, Huffman::new(&lengths[hlit..])?))
+}
+
+// Decode a raw DEFLATE stream (as stored in zip entries); None if malformed
+pub(crate) fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut r = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let last = r.read(1)? == 1;
+        match r.read(2)? {
+            0 => {
+                r.align();
+                let len = r.read(16)? as usize;
+                let nlen = r.read(16)? as usize;
+                if len != !nlen & 0xffff {
+                    return None;
+                }
+                for _ in 0..len {
+                    out.push(r.read(8)? as u8);
+                }
+            }
+            kind @ (1 | 2) => {
+                let (lit, dist) = if kind == 1 { fixed_tables() } else { dynamic_tables(&mut r)? };
+                loop {
+                    let symbol = lit.decode(&mut r)? as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                    } else if symbol == 256 {
+                        break;
+                    } else {
+                        let li = symbol - 257;
+                        let len = *LENGTH_BASE.get(li)? as usize + r.read(*LENGTH_EXTRA.get(li)? as u32)? as usize;
+                        let di = dist.decode(&mut r)? as usize;
+                        let distance = *DIST_BASE.get(di)? as usize + r.read(*DIST_EXTRA.get(di)? as u32)? as usize;
+                        let start = out.len().checked_sub(distance)?;
+                        for k in 0..len {
+                            out.push(out[start + k]);
+                        }
+                    }
+                }
+            }
+            _ => return None,
+        }
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+// Strip the gzip wrapper and inflate the member inside it
+pub(crate) fn gunzip(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 {
+        return None;
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & 0x04 != 0 {
+        let xlen = u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+        pos += 2 + xlen;
+    }
+    for flag in [0x08, 0x10] {
+        // Zero-terminated file name / comment
+        if flags & flag != 0 {
+            pos += data.get(pos..)?.iter().position(|&b| b == 0)? + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+    inflate(data.get(pos..)?)
+}
@@ -0,0 +1,107 @@
+use crate::http_date::parse_http_date;
+use crate::request::Request;
+use crate::response::Response;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Strong ETag for a numeric resource version, e.g. a row's version column
+pub fn version_etag(version: u64) -> String {
+    format!("\"v{}\"", version)
+}
+
+// Strong ETag derived from a representation's bytes (FNV-1a)
+pub fn content_etag(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("\"{:016x}\"", hash)
+}
+
+// Enforce If-Match against the resource's current ETag before an update.
+// A missing header passes; use `RouteHandle::require_if_match` to demand one.
+// On mismatch the Err holds a ready 412 response carrying the current ETag.
+pub fn check_if_match(req: &Request, current_etag: &str) -> Result<(), Response> {
+    let Some(if_match) = req.header("if-match") else {
+        return Ok(());
+    };
+    if if_match_satisfied(if_match, current_etag) {
+        Ok(())
+    } else {
+        Err(Response::new(
+            412,
+            b"Precondition Failed: the resource has changed".to_vec(),
+            "text/plain",
+        )
+        .with_header("ETag", current_etag))
+    }
+}
+
+// If-Match uses the strong comparison: weak tags never match (RFC 9110 section 13.1.1)
+fn if_match_satisfied(header: &str, current_etag: &str) -> bool {
+    if header.trim() == "*" {
+        return true;
+    }
+    if current_etag.starts_with("W/") {
+        return false;
+    }
+    header
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| !tag.starts_with("W/") && tag == current_etag)
+}
+
+// True if the client's cached copy is current, so a GET or HEAD can be
+// answered with 304 Not Modified. If-None-Match is checked against `etag`
+// with the weak comparison; only without it is If-Modified-Since checked
+// against `last_modified`, to the second (RFC 9110 section 13.2.2).
+pub fn not_modified(req: &Request, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+    if !matches!(req.method.as_str(), "GET" | "HEAD") {
+        return false;
+    }
+    if let Some(if_none_match) = req.header("if-none-match") {
+        let Some(etag) = etag else {
+            return false;
+        };
+        if if_none_match.trim() == "*" {
+            return true;
+        }
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        return if_none_match.split(',').any(|tag| opaque(tag) == opaque(etag));
+    }
+    let (Some(since), Some(modified)) = (
+        req.header("if-modified-since").and_then(|v| parse_http_date(v)),
+        last_modified,
+    ) else {
+        return false;
+    };
+    let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    secs(modified) <= secs(since)
+}
+
+// Turn a 200 response to a GET/HEAD into 304 if its own ETag/Last-Modified
+// headers show the client's copy is current
+pub(crate) fn apply_not_modified(req: &Request, mut response: Response) -> Response {
+    if response.status_code != 200 {
+        return response;
+    }
+    let etag = response.header("etag").cloned();
+    let last_modified = response.header("last-modified").and_then(|v| parse_http_date(v));
+    if etag.is_none() && last_modified.is_none() {
+        return response;
+    }
+    if not_modified(req, etag.as_deref(), last_modified) {
+        response.status_code = 304;
+        response.clear_body();
+    }
+    response
+}
+
+pub(crate) fn precondition_required() -> Response {
+    Response::new(
+        428,
+        b"Precondition Required: send If-Match with the resource's current ETag".to_vec(),
+        "text/plain",
+    )
+}
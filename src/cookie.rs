@@ -0,0 +1,159 @@
+// Cookies for Set-Cookie response headers:
+//
+//     response.set_cookie(
+//         Cookie::new("theme", "dark")
+//             .http_only()
+//             .secure()
+//             .same_site(SameSite::Lax)
+//             .max_age(3600),
+//     )
+
+use crate::http_date::http_date;
+
+use std::fmt;
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    // Sent on cross-site requests too; browsers require Secure with it, so
+    // it is added
+    None,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<i64>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    // Panics if `name` isn't a valid token (empty, or has spaces,
+    // separators or control characters). Bytes of `value` a cookie can't
+    // hold (space, quote, comma, semicolon, backslash, controls) are sent
+    // percent-encoded.
+    pub fn new(name: &str, value: &str) -> Self {
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            panic!("invalid cookie name {:?}", name);
+        }
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(attribute_value(path));
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(attribute_value(domain));
+        self
+    }
+
+    // Lifetime in seconds; 0 or less deletes the cookie
+    pub fn max_age(mut self, seconds: i64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    // For old clients without Max-Age support; newer ones prefer Max-Age
+    pub fn expires(mut self, at: SystemTime) -> Self {
+        self.expires = Some(at);
+        self
+    }
+
+    // Only sent over HTTPS
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    // Hidden from document.cookie
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+// The Set-Cookie header value
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.name)?;
+        for b in self.value.bytes() {
+            if is_cookie_octet(b) {
+                write!(f, "{}", b as char)?;
+            } else {
+                write!(f, "%{:02X}", b)?;
+            }
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.max(0))?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if self.secure || self.same_site == Some(SameSite::None) {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax"),
+            Some(SameSite::None) => f.write_str("; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+// RFC 6265 cookie-octet: printable ASCII but for DQUOTE , ; and backslash.
+// Anything above 0x7E is UTF-8 and sent encoded too.
+fn is_cookie_octet(b: u8) -> bool {
+    matches!(b, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// Path and Domain end at the next `;`, and a line break would end the header
+fn attribute_value(value: &str) -> String {
+    value.replace([';', '\r', '\n'], "")
+}
@@ -0,0 +1,140 @@
+// Cross-origin resource sharing, so browser frontends on other origins can
+// call the server:
+//
+//     server.cors(
+//         Cors::new()
+//             .allow_origin("https://app.example.com")
+//             .allow_methods(&["GET", "POST", "DELETE"])
+//             .allow_headers(&["Content-Type", "Authorization"])
+//             .allow_credentials()
+//             .max_age(Duration::from_secs(600)),
+//     );
+//
+// Preflight OPTIONS requests from allowed origins are answered before any
+// middleware runs; every other response to an allowed origin gets the
+// Access-Control-Allow-* headers.
+
+use crate::request::Request;
+use crate::response::Response;
+
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    expose: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            origins: Vec::new(),
+            methods: ["GET", "HEAD", "POST"].map(String::from).to_vec(),
+            headers: Vec::new(),
+            expose: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl Cors {
+    // No origin is allowed until one is added
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // An origin as browsers send it, e.g. "https://app.example.com"; "*"
+    // allows any
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.trim_end_matches('/').to_ascii_lowercase());
+        self
+    }
+
+    // Methods a preflight may ask for; GET, HEAD and POST by default
+    pub fn allow_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = methods.iter().map(|m| m.to_uppercase()).collect();
+        self
+    }
+
+    // Request headers beyond the CORS-safelisted ones, e.g. Authorization;
+    // "*" allows whatever the preflight asks for
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|h| h.to_string()).collect();
+        self
+    }
+
+    // Response headers scripts may read beyond the safelisted ones
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.expose = headers.iter().map(|h| h.to_string()).collect();
+        self
+    }
+
+    // Let requests carry cookies and Authorization. The origin is then
+    // echoed back even when "*" is allowed, as browsers require.
+    pub fn allow_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
+    // How long browsers may cache a preflight answer
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    // The Origin of a cross-origin request this allows
+    fn allowed_origin<'a>(&self, req: &'a Request) -> Option<&'a str> {
+        let origin = req.header("origin")?.trim();
+        let normalized = origin.to_ascii_lowercase();
+        self.origins
+            .iter()
+            .any(|allowed| allowed == "*" || *allowed == normalized)
+            .then_some(origin)
+    }
+
+    // 204 for a preflight from an allowed origin; None for anything else,
+    // which is then handled as usual
+    pub(crate) fn preflight(&self, req: &Request) -> Option<Response> {
+        if req.method != "OPTIONS" || req.header("access-control-request-method").is_none() {
+            return None;
+        }
+        self.allowed_origin(req)?;
+        let mut response = Response::no_content().with_header("Access-Control-Allow-Methods", &self.methods.join(", "));
+        let headers = match req.header("access-control-request-headers") {
+            Some(requested) if self.headers.iter().any(|h| h == "*") => requested.clone(),
+            _ => self.headers.join(", "),
+        };
+        if !headers.is_empty() {
+            response = response.with_header("Access-Control-Allow-Headers", &headers);
+        }
+        if let Some(max_age) = self.max_age {
+            response = response.with_header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+        Some(response)
+    }
+
+    // Access-Control-Allow-Origin and friends for an allowed origin
+    pub(crate) fn decorate(&self, req: &Request, response: &mut Response) {
+        // Whether the answer depends on Origin, for caches
+        if !self.origins.iter().all(|allowed| allowed == "*") || self.credentials {
+            response.headers.append("Vary", "Origin");
+        }
+        let Some(origin) = self.allowed_origin(req) else {
+            return;
+        };
+        let any = self.origins.iter().any(|allowed| allowed == "*");
+        let allow = if any && !self.credentials { "*" } else { origin };
+        response.headers.insert("Access-Control-Allow-Origin", allow);
+        if self.credentials {
+            response.headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+        if !self.expose.is_empty() {
+            response.headers.insert("Access-Control-Expose-Headers", self.expose.join(", "));
+        }
+    }
+}
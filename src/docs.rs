@@ -0,0 +1,66 @@
+// HTML page listing the route table, served at /_rake/routes
+
+use crate::router::{RouteInfo, ANY_METHOD};
+
+// What the page shows for one route
+pub(crate) struct RouteDoc<'a> {
+    pub(crate) route: RouteInfo,
+    pub(crate) description: Option<&'a str>,
+    pub(crate) notes: Vec<&'static str>,
+}
+
+pub(crate) fn render(routes: &[RouteDoc]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Routes</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; }\n\
+         th, td { border: 1px solid #ccc; padding: 0.4em 0.8em; text-align: left; vertical-align: top; }\n\
+         code { font-size: 1.05em; }\n\
+         </style>\n</head>\n<body>\n<h1>Routes</h1>\n",
+    );
+    if routes.is_empty() {
+        html.push_str("<p>No routes registered.</p>\n");
+    } else {
+        html.push_str(
+            "<table>\n<tr><th>Method</th><th>Path</th><th>Name</th><th>Parameters</th><th>Description</th></tr>\n",
+        );
+        for doc in routes {
+            let params: Vec<String> = doc
+                .route
+                .params
+                .iter()
+                .map(|(name, kind)| format!("<code>{}</code>: {}", escape(name), escape(kind)))
+                .collect();
+            let mut description = doc.description.map(escape).unwrap_or_default();
+            for note in &doc.notes {
+                if !description.is_empty() {
+                    description.push_str("<br>");
+                }
+                description.push_str(&format!("<em>{}</em>", escape(note)));
+            }
+            let method = if doc.route.method == ANY_METHOD {
+                "ANY"
+            } else {
+                &doc.route.method
+            };
+            html.push_str(&format!(
+                "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape(method),
+                escape(&doc.route.pattern),
+                doc.route.name.as_deref().map(escape).unwrap_or_default(),
+                params.join("<br>"),
+                description
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,165 @@
+// An error a handler returns instead of building its own error response:
+//
+//     server.get("/user", |req, _| -> Result<Response, rake::Error> {
+//         let id: u64 = req.param_as("id")?.ok_or_else(|| Error::bad_request("id is required"))?;
+//         let user = find_user(id).ok_or_else(|| Error::not_found("no such user"))?;
+//         Ok(Response::json(&user))
+//     });
+//
+// Sent as text/plain unless the server has an error_renderer.
+
+use crate::param::ParamError;
+use crate::response::{IntoResponse, Response};
+use crate::text::TextError;
+use crate::validate::ValidationErrors;
+
+use std::fmt;
+use std::io;
+
+// to_response() and IntoResponse for the errors of request extractors
+// (req.json(), req.param_as() and the like), from their status() and
+// Display: handlers can return them with `?` and the client gets the
+// status with the message as text/plain
+macro_rules! error_response {
+    ($error:ty) => {
+        impl $error {
+            pub fn to_response(&self) -> $crate::response::Response {
+                $crate::response::Response::new(self.status(), self.to_string().into_bytes(), "text/plain")
+            }
+        }
+
+        impl $crate::response::IntoResponse for $error {
+            fn into_response(self) -> $crate::response::Response {
+                self.to_response()
+            }
+        }
+    };
+}
+
+pub(crate) use error_response;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    status: u16,
+    message: String,
+}
+
+impl Error {
+    // `status` should be a 4xx or 5xx code
+    pub fn new(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Self::new(400, message)
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        Self::new(401, message)
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        Self::new(403, message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Self::new(404, message)
+    }
+
+    pub fn conflict(message: &str) -> Self {
+        Self::new(409, message)
+    }
+
+    pub fn internal(message: &str) -> Self {
+        Self::new(500, message)
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    // Shown to the client, so it shouldn't carry internal details
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    // Back from the response into_response made of it
+    pub(crate) fn from_response(response: &Response) -> Self {
+        Self {
+            status: response.status_code,
+            message: String::from_utf8_lossy(&response.body).into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+// The message as text/plain with the status. The response remembers it
+// came from an Error, so the server's error_renderer can replace it.
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let mut response = Response::builder()
+            .status(self.status)
+            .text(&self.message)
+            .build();
+        response.from_error = true;
+        response
+    }
+}
+
+impl From<ParamError> for Error {
+    fn from(e: ParamError) -> Self {
+        Self::new(e.status(), &e.to_string())
+    }
+}
+
+impl From<TextError> for Error {
+    fn from(e: TextError) -> Self {
+        Self::new(e.status(), &e.to_string())
+    }
+}
+
+impl From<ValidationErrors> for Error {
+    fn from(e: ValidationErrors) -> Self {
+        Self::new(422, &e.to_string())
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<crate::json::JsonError> for Error {
+    fn from(e: crate::json::JsonError) -> Self {
+        Self::new(e.status(), &e.to_string())
+    }
+}
+
+#[cfg(feature = "xml")]
+impl From<crate::xml::XmlError> for Error {
+    fn from(e: crate::xml::XmlError) -> Self {
+        Self::new(e.status(), &e.to_string())
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl From<crate::protobuf::ProtobufError> for Error {
+    fn from(e: crate::protobuf::ProtobufError) -> Self {
+        Self::new(e.status(), &e.to_string())
+    }
+}
+
+// A failed read or write is the server's problem; the details are logged
+// rather than sent
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        eprintln!("Handler I/O error: {}", e);
+        Self::internal("Internal Server Error")
+    }
+}
@@ -0,0 +1,157 @@
+// Bracketed form field names, as HTML form libraries emit them:
+// user[name]=x&user[tags][]=a&user[tags][]=b
+
+use crate::request::{urlencoded_pairs, Request};
+
+use std::collections::HashMap;
+
+// Deeper keys are taken literally, so a body of `a[][][]…` can't recurse
+// the handler's thread out of stack
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FormValue {
+    Text(String),
+    // From `name[]` keys, in the order sent
+    List(Vec<FormValue>),
+    // From `name[key]` keys
+    Map(HashMap<String, FormValue>),
+}
+
+impl FormValue {
+    // Member of a Map
+    pub fn get(&self, key: &str) -> Option<&FormValue> {
+        match self {
+            FormValue::Map(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FormValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[FormValue]> {
+        match self {
+            FormValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&HashMap<String, FormValue>> {
+        match self {
+            FormValue::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    // Set the value at `path` below self, turning whatever is in the way
+    // into the Map or List the path needs
+    fn insert(&mut self, path: &[&str], value: String) {
+        let Some((first, rest)) = path.split_first() else {
+            *self = FormValue::Text(value);
+            return;
+        };
+        if first.is_empty() {
+            if !matches!(self, FormValue::List(_)) {
+                *self = FormValue::List(Vec::new());
+            }
+            let FormValue::List(list) = self else { unreachable!() };
+            // a[][x]=1&a[][y]=2 fills one element until a key repeats
+            if let ([key, ..], Some(FormValue::Map(last))) = (rest, list.last_mut())
+                && !key.is_empty()
+                && !last.contains_key(*key)
+            {
+                list.last_mut().unwrap().insert(rest, value);
+                return;
+            }
+            let mut element = FormValue::Text(String::new());
+            element.insert(rest, value);
+            list.push(element);
+        } else {
+            if !matches!(self, FormValue::Map(_)) {
+                *self = FormValue::Map(HashMap::new());
+            }
+            let FormValue::Map(map) = self else { unreachable!() };
+            map.entry(first.to_string())
+                .or_insert_with(|| FormValue::Text(String::new()))
+                .insert(rest, value);
+        }
+    }
+}
+
+// `user[tags][]` as ["user", "tags", ""]. A key with unbalanced brackets,
+// or nested more than MAX_DEPTH levels, is taken literally as one name.
+fn key_path(key: &str) -> Vec<&str> {
+    let Some(open) = key.find('[').filter(|&open| open > 0 && key.ends_with(']')) else {
+        return vec![key];
+    };
+    let mut path = vec![&key[..open]];
+    let mut rest = &key[open..];
+    while let Some(inner) = rest.strip_prefix('[') {
+        let Some(close) = inner.find(']') else {
+            return vec![key];
+        };
+        if path.len() > MAX_DEPTH {
+            return vec![key];
+        }
+        path.push(&inner[..close]);
+        rest = &inner[close + 1..];
+    }
+    if rest.is_empty() { path } else { vec![key] }
+}
+
+// Pairs of a urlencoded body or query as a nested Map
+fn parse_nested(data: &[u8]) -> FormValue {
+    let mut root = FormValue::Map(HashMap::new());
+    for (key, value) in urlencoded_pairs(data) {
+        root.insert(&key_path(&key), value);
+    }
+    root
+}
+
+impl Request {
+    // The form body with bracketed names grouped, e.g. for
+    // user[name]=x&user[tags][]=a&user[tags][]=b:
+    // form_nested().get("user")?.get("tags")?.as_list() has "a" and "b".
+    // Always a Map at the top; form_data keeps the flat view.
+    pub fn form_nested(&self) -> FormValue {
+        parse_nested(&self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_bracketed_keys() {
+        let form = parse_nested(b"user[name]=x&user[tags][]=a&user[tags][]=b");
+        let user = form.get("user").unwrap();
+        assert_eq!(user.get("name").and_then(FormValue::as_str), Some("x"));
+        let tags: Vec<_> = user.get("tags").unwrap().as_list().unwrap().iter().map(|t| t.as_str()).collect();
+        assert_eq!(tags, [Some("a"), Some("b")]);
+    }
+
+    #[test]
+    fn deeply_nested_key_is_taken_literally() {
+        let key = format!("a{}", "[]".repeat(1_000_000));
+        let form = parse_nested(format!("{}=1", key).as_bytes());
+        assert_eq!(form.get(&key).and_then(FormValue::as_str), Some("1"));
+        assert!(form.get("a").is_none());
+    }
+
+    #[test]
+    fn nesting_up_to_the_limit_is_kept() {
+        let key = format!("a{}", "[x]".repeat(MAX_DEPTH));
+        let form = parse_nested(format!("{}=1", key).as_bytes());
+        let mut value = form.get("a").unwrap();
+        for _ in 0..MAX_DEPTH {
+            value = value.get("x").unwrap();
+        }
+        assert_eq!(value.as_str(), Some("1"));
+    }
+}
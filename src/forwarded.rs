@@ -0,0 +1,99 @@
+// Finding the real client address behind reverse proxies
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+// A proxy address or network, e.g. "10.0.0.1" or "10.0.0.0/8"
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TrustedProxy {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl TrustedProxy {
+    pub(crate) fn parse(value: &str) -> Option<TrustedProxy> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr.trim().parse().ok()?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|&p| p <= bits)?,
+            None => bits,
+        };
+        Some(TrustedProxy { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4-mapped IPv6 peers of a dual-stack socket count as IPv4
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// The client's address: the peer itself unless it is a trusted proxy, in
+// which case the forwarding chain (Forwarded, else X-Forwarded-For) is
+// walked from the nearest hop outwards to the first untrusted address. A
+// chain that is all trusted proxies yields its first entry.
+pub(crate) fn client_ip(peer: Option<SocketAddr>, headers: &HashMap<String, String>, trusted: &[TrustedProxy]) -> Option<IpAddr> {
+    let peer = peer?.ip();
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|proxy| proxy.contains(ip));
+    if !is_trusted(peer) {
+        return Some(peer);
+    }
+    let chain = match headers.get("forwarded") {
+        Some(value) => forwarded_for(value),
+        None => match headers.get("x-forwarded-for") {
+            Some(value) => value.split(',').map(parse_node).collect(),
+            None => return Some(peer),
+        },
+    };
+    let mut client = peer;
+    for hop in chain.into_iter().rev() {
+        // An obfuscated or unparseable hop can't be checked, so stop there
+        let Some(ip) = hop else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+// The for= address of each Forwarded element (RFC 7239), in order
+fn forwarded_for(value: &str) -> Vec<Option<IpAddr>> {
+    value
+        .split(',')
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| parse_node(node))
+        })
+        .collect()
+}
+
+// "192.0.2.1", "192.0.2.1:8080", "\"[2001:db8::1]:8080\"" or "2001:db8::1"
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
@@ -0,0 +1,1015 @@
+// HTTP/2 over cleartext TCP (RFC 9113), for clients that open with the
+// connection preface or switch to it with `Upgrade: h2c`. One thread reads
+// frames, the connection's own thread keeps the stream state and does all
+// the writing, and each request is answered on a thread of its own so a
+// slow handler doesn't hold up the other streams.
+
+use crate::hpack::{self, Decoder};
+use crate::response::Response;
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem;
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, Scope};
+use std::time::Duration;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame types
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// Error codes
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+// Settings
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// The largest frame we take, which is also where the client starts
+const MAX_FRAME_SIZE: usize = 16_384;
+const MAX_STREAMS: usize = 100;
+// What the client may send us, per stream and on the connection. It's
+// topped back up as data is read, so uploads aren't held to 64 KiB round
+// trips.
+const RECV_WINDOW: u32 = 1 << 20;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+// Decoded request fields, counted as in SETTINGS_MAX_HEADER_LIST_SIZE
+const MAX_HEADER_LIST_SIZE: usize = 16 * 1024;
+// Compressed header block across its CONTINUATION frames
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+// Streamed response bodies are read this much at a time, with at most
+// this many chunks waiting on the client's flow-control window
+const CHUNK_SIZE: usize = 16 * 1024;
+const QUEUED_CHUNKS: usize = 4;
+
+// A request that came in on a stream, for the server to answer
+pub(crate) struct H2Request {
+    pub method: String,
+    pub target: String,
+    // Regular fields in order, lower-case; :authority is given as host
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub trailers: HashMap<String, String>,
+    // 413 or 431 when the body or header fields were over the limits.
+    // The body has been dropped then.
+    pub error: Option<u16>,
+}
+
+// The HTTP/1.1 request an `Upgrade: h2c` came on, answered as stream 1
+pub(crate) struct Upgrade<'a> {
+    // The decoded HTTP2-Settings header
+    pub settings: Vec<u8>,
+    pub head_request: bool,
+    pub respond: Box<dyn FnOnce() -> Response + Send + 'a>,
+}
+
+// Runs the connection until the client goes away, errs, or sits idle for
+// `idle_timeout` with nothing open. `preread` is whatever was read past
+// the HTTP/1.1 head, starting with the preface.
+pub(crate) fn serve<'a, F>(
+    stream: TcpStream,
+    preread: Vec<u8>,
+    idle_timeout: Option<Duration>,
+    max_body_size: Option<usize>,
+    upgrade: Option<Upgrade<'a>>,
+    respond: &'a F,
+) where
+    F: Fn(H2Request) -> Response + Sync,
+{
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let _ = reader.set_read_timeout(idle_timeout);
+    thread::scope(|scope| {
+        let (events, inbox) = mpsc::channel();
+        let frames = events.clone();
+        scope.spawn(move || read_frames(reader, preread, frames));
+        let mut connection = Connection {
+            writer: Writer {
+                out: stream,
+                broken: false,
+            },
+            scope,
+            respond,
+            events,
+            decoder: Decoder::default(),
+            streams: HashMap::new(),
+            last_stream: 0,
+            continuation: None,
+            send_window: DEFAULT_WINDOW,
+            stream_window: DEFAULT_WINDOW,
+            frame_size: MAX_FRAME_SIZE,
+            max_body_size,
+            going_away: false,
+        };
+        connection.run(upgrade, inbox);
+        let _ = connection.writer.out.shutdown(Shutdown::Both);
+    });
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+enum Event {
+    Frame(Frame),
+    // The client closed the connection
+    Closed,
+    // Nothing came in within the idle timeout
+    Idle,
+    // Framing the connection can't go on from
+    Error(u32),
+    // A handler's answer: the stream, whether it was HEAD, and the body
+    // chunks when the response streams one
+    Response(u32, Response, bool, Option<Receiver<io::Result<Vec<u8>>>>),
+    // More of a streamed body is queued, or it's done
+    Body,
+}
+
+fn read_frames(mut stream: TcpStream, mut buffer: Vec<u8>, events: Sender<Event>) {
+    let mut preface = false;
+    let mut chunk = vec![0; MAX_FRAME_SIZE];
+    loop {
+        if !preface {
+            if buffer.len() >= PREFACE.len() {
+                if !buffer.starts_with(PREFACE) {
+                    let _ = events.send(Event::Error(PROTOCOL_ERROR));
+                    return;
+                }
+                buffer.drain(..PREFACE.len());
+                preface = true;
+                continue;
+            }
+            if !PREFACE.starts_with(&buffer) {
+                let _ = events.send(Event::Error(PROTOCOL_ERROR));
+                return;
+            }
+        } else if buffer.len() >= 9 {
+            let len = u32::from_be_bytes([0, buffer[0], buffer[1], buffer[2]]) as usize;
+            if len > MAX_FRAME_SIZE {
+                let _ = events.send(Event::Error(FRAME_SIZE_ERROR));
+                return;
+            }
+            if buffer.len() >= 9 + len {
+                let frame = Frame {
+                    kind: buffer[3],
+                    flags: buffer[4],
+                    stream: u32::from_be_bytes([buffer[5], buffer[6], buffer[7], buffer[8]])
+                        & 0x7fff_ffff,
+                    payload: buffer[9..9 + len].to_vec(),
+                };
+                buffer.drain(..9 + len);
+                if events.send(Event::Frame(frame)).is_err() {
+                    return;
+                }
+                continue;
+            }
+        }
+        match stream.read(&mut chunk) {
+            Ok(0) => {
+                let _ = events.send(Event::Closed);
+                return;
+            }
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if events.send(Event::Idle).is_err() {
+                    return;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => {
+                let _ = events.send(Event::Closed);
+                return;
+            }
+        }
+    }
+}
+
+struct Writer {
+    out: TcpStream,
+    // A write failed; the client is gone
+    broken: bool,
+}
+
+impl Writer {
+    fn frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+        if self.broken {
+            return;
+        }
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        if self.out.write_all(&frame).is_err() {
+            self.broken = true;
+        }
+    }
+
+    fn reset(&mut self, stream: u32, code: u32) {
+        self.frame(RST_STREAM, 0, stream, &code.to_be_bytes());
+    }
+
+    fn window_update(&mut self, stream: u32, increment: usize) {
+        self.frame(WINDOW_UPDATE, 0, stream, &(increment as u32).to_be_bytes());
+    }
+}
+
+#[derive(PartialEq)]
+enum Recv {
+    // The request is still coming in
+    Open,
+    // Answered already; the rest of the body is dropped
+    Discard,
+    // END_STREAM seen
+    Closed,
+}
+
+struct Stream {
+    recv: Recv,
+    fields: Vec<(String, String)>,
+    body: Vec<u8>,
+    trailers: HashMap<String, String>,
+    send_window: i64,
+    out: Option<Outgoing>,
+}
+
+// A response body being sent, as the flow-control windows allow
+struct Outgoing {
+    pending: Vec<u8>,
+    sent: usize,
+    // The rest of a streamed body
+    source: Option<Receiver<io::Result<Vec<u8>>>>,
+}
+
+struct Connection<'scope, 'env, F> {
+    writer: Writer,
+    scope: &'scope Scope<'scope, 'env>,
+    respond: &'env F,
+    events: Sender<Event>,
+    decoder: Decoder,
+    streams: HashMap<u32, Stream>,
+    // The highest stream the client has opened
+    last_stream: u32,
+    // A header block still waiting on CONTINUATION frames, with whether
+    // its HEADERS frame ended the stream
+    continuation: Option<(u32, Vec<u8>, bool)>,
+    send_window: i64,
+    // The client's SETTINGS_INITIAL_WINDOW_SIZE and SETTINGS_MAX_FRAME_SIZE
+    stream_window: i64,
+    frame_size: usize,
+    max_body_size: Option<usize>,
+    // The client sent GOAWAY; finish what's open, then close
+    going_away: bool,
+}
+
+impl<'scope, 'env: 'scope, F> Connection<'scope, 'env, F>
+where
+    F: Fn(H2Request) -> Response + Sync,
+{
+    fn run(&mut self, upgrade: Option<Upgrade<'env>>, inbox: Receiver<Event>) {
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_STREAMS as u32),
+            (SETTINGS_INITIAL_WINDOW_SIZE, RECV_WINDOW),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST_SIZE as u32),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+        self.writer.frame(SETTINGS, 0, 0, &settings);
+        self.writer.window_update(0, RECV_WINDOW as usize - DEFAULT_WINDOW as usize);
+
+        if let Some(upgrade) = upgrade {
+            if let Err(code) = self.apply_settings(&upgrade.settings) {
+                self.go_away(code);
+                return;
+            }
+            self.last_stream = 1;
+            self.streams.insert(1, self.new_stream(Recv::Closed));
+            self.spawn(1, upgrade.head_request, upgrade.respond);
+        }
+
+        while let Ok(event) = inbox.recv() {
+            let result = match event {
+                Event::Frame(frame) => self.on_frame(frame),
+                Event::Response(id, response, head_request, body) => {
+                    self.on_response(id, response, head_request, body);
+                    Ok(())
+                }
+                Event::Body => {
+                    self.flush();
+                    Ok(())
+                }
+                Event::Idle if self.streams.is_empty() => Err(NO_ERROR),
+                Event::Idle => Ok(()),
+                Event::Closed => return,
+                Event::Error(code) => Err(code),
+            };
+            if let Err(code) = result {
+                self.go_away(code);
+                return;
+            }
+            if self.going_away && self.streams.is_empty() {
+                self.go_away(NO_ERROR);
+                return;
+            }
+            if self.writer.broken {
+                return;
+            }
+        }
+    }
+
+    fn new_stream(&self, recv: Recv) -> Stream {
+        Stream {
+            recv,
+            fields: Vec::new(),
+            body: Vec::new(),
+            trailers: HashMap::new(),
+            send_window: self.stream_window,
+            out: None,
+        }
+    }
+
+    fn go_away(&mut self, code: u32) {
+        let mut payload = self.last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        self.writer.frame(GOAWAY, 0, 0, &payload);
+    }
+
+    // Errors are connection errors, to be sent in GOAWAY; stream errors
+    // are answered here with RST_STREAM
+    fn on_frame(&mut self, frame: Frame) -> Result<(), u32> {
+        if let Some((id, ..)) = &self.continuation
+            && (frame.kind != CONTINUATION || frame.stream != *id)
+        {
+            return Err(PROTOCOL_ERROR);
+        }
+        match frame.kind {
+            DATA => self.on_data(frame),
+            HEADERS => self.on_headers(frame),
+            CONTINUATION => self.on_continuation(frame),
+            PRIORITY => {
+                if frame.stream == 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if frame.payload.len() != 5 {
+                    self.reset(frame.stream, FRAME_SIZE_ERROR);
+                }
+                Ok(())
+            }
+            RST_STREAM => {
+                if frame.stream == 0 || frame.stream > self.last_stream {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if frame.payload.len() != 4 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                self.streams.remove(&frame.stream);
+                Ok(())
+            }
+            SETTINGS => {
+                if frame.stream != 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if frame.flags & ACK != 0 {
+                    return if frame.payload.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(FRAME_SIZE_ERROR)
+                    };
+                }
+                self.apply_settings(&frame.payload)?;
+                self.writer.frame(SETTINGS, ACK, 0, &[]);
+                self.flush();
+                Ok(())
+            }
+            // We never enable push, so clients can't send it either
+            PUSH_PROMISE => Err(PROTOCOL_ERROR),
+            PING => {
+                if frame.stream != 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if frame.payload.len() != 8 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                if frame.flags & ACK == 0 {
+                    self.writer.frame(PING, ACK, 0, &frame.payload);
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                if frame.stream != 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                self.going_away = true;
+                Ok(())
+            }
+            WINDOW_UPDATE => self.on_window_update(frame),
+            // Unknown frame types are ignored
+            _ => Ok(()),
+        }
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), u32> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(FRAME_SIZE_ERROR);
+        }
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(PROTOCOL_ERROR),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW {
+                        return Err(FLOW_CONTROL_ERROR);
+                    }
+                    let delta = value - self.stream_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(FLOW_CONTROL_ERROR);
+                        }
+                    }
+                    self.stream_window = value;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(MAX_FRAME_SIZE as u32..=(1 << 24) - 1).contains(&value) {
+                        return Err(PROTOCOL_ERROR);
+                    }
+                    self.frame_size = value as usize;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, frame: Frame) -> Result<(), u32> {
+        let Ok(bytes) = <[u8; 4]>::try_from(&frame.payload[..]) else {
+            return Err(FRAME_SIZE_ERROR);
+        };
+        let increment = (u32::from_be_bytes(bytes) & 0x7fff_ffff) as i64;
+        if frame.stream == 0 {
+            if increment == 0 {
+                return Err(PROTOCOL_ERROR);
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW {
+                return Err(FLOW_CONTROL_ERROR);
+            }
+        } else if let Some(stream) = self.streams.get_mut(&frame.stream) {
+            if increment == 0 {
+                self.reset(frame.stream, PROTOCOL_ERROR);
+                return Ok(());
+            }
+            stream.send_window += increment;
+            if stream.send_window > MAX_WINDOW {
+                self.reset(frame.stream, FLOW_CONTROL_ERROR);
+                return Ok(());
+            }
+        } else if frame.stream > self.last_stream {
+            return Err(PROTOCOL_ERROR);
+        }
+        self.flush();
+        Ok(())
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<(), u32> {
+        let id = frame.stream;
+        if id == 0 {
+            return Err(PROTOCOL_ERROR);
+        }
+        // Padding counts against the connection window too
+        if !frame.payload.is_empty() {
+            self.writer.window_update(0, frame.payload.len());
+        }
+        let data = unpad(&frame, false)?;
+        let end_stream = frame.flags & END_STREAM != 0;
+        let Some(stream) = self.streams.get_mut(&id) else {
+            // Frames on a stream we've closed may still be in flight
+            return if id > self.last_stream {
+                Err(PROTOCOL_ERROR)
+            } else {
+                Ok(())
+            };
+        };
+        match stream.recv {
+            Recv::Open => {}
+            Recv::Discard => {
+                if end_stream {
+                    stream.recv = Recv::Closed;
+                }
+                return Ok(());
+            }
+            Recv::Closed => {
+                self.reset(id, STREAM_CLOSED);
+                return Ok(());
+            }
+        }
+        stream.body.extend_from_slice(data);
+        if let Some(max) = self.max_body_size
+            && stream.body.len() > max
+        {
+            stream.body = Vec::new();
+            stream.recv = if end_stream {
+                Recv::Closed
+            } else {
+                Recv::Discard
+            };
+            self.dispatch(id, Some(413));
+        } else if end_stream {
+            stream.recv = Recv::Closed;
+            self.dispatch(id, None);
+        } else if !frame.payload.is_empty() {
+            self.writer.window_update(id, frame.payload.len());
+        }
+        Ok(())
+    }
+
+    fn on_headers(&mut self, frame: Frame) -> Result<(), u32> {
+        if frame.stream == 0 || frame.stream.is_multiple_of(2) {
+            return Err(PROTOCOL_ERROR);
+        }
+        let block = unpad(&frame, true)?.to_vec();
+        let end_stream = frame.flags & END_STREAM != 0;
+        if frame.flags & END_HEADERS == 0 {
+            self.continuation = Some((frame.stream, block, end_stream));
+            return Ok(());
+        }
+        self.on_header_block(frame.stream, &block, end_stream)
+    }
+
+    fn on_continuation(&mut self, frame: Frame) -> Result<(), u32> {
+        let Some((id, mut block, end_stream)) = self.continuation.take() else {
+            return Err(PROTOCOL_ERROR);
+        };
+        block.extend_from_slice(&frame.payload);
+        if block.len() > MAX_HEADER_BLOCK {
+            return Err(ENHANCE_YOUR_CALM);
+        }
+        if frame.flags & END_HEADERS == 0 {
+            self.continuation = Some((id, block, end_stream));
+            return Ok(());
+        }
+        self.on_header_block(id, &block, end_stream)
+    }
+
+    fn on_header_block(&mut self, id: u32, block: &[u8], end_stream: bool) -> Result<(), u32> {
+        // Decoded even for streams we drop, to keep the table in step
+        let fields = self.decoder.decode(block).map_err(|_| COMPRESSION_ERROR)?;
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            // Trailers, which have to end the request
+            match stream.recv {
+                Recv::Open => {}
+                Recv::Discard if end_stream => {
+                    stream.recv = Recv::Closed;
+                    return Ok(());
+                }
+                _ => {
+                    self.reset(id, STREAM_CLOSED);
+                    return Ok(());
+                }
+            }
+            if !end_stream || fields.iter().any(|(name, _)| name.starts_with(':')) {
+                self.reset(id, PROTOCOL_ERROR);
+                return Ok(());
+            }
+            stream.trailers = fields.into_iter().collect();
+            stream.recv = Recv::Closed;
+            self.dispatch(id, None);
+            return Ok(());
+        }
+        if id <= self.last_stream {
+            return Ok(());
+        }
+        self.last_stream = id;
+        if self.going_away {
+            return Ok(());
+        }
+        if self.streams.len() >= MAX_STREAMS {
+            self.writer.reset(id, REFUSED_STREAM);
+            return Ok(());
+        }
+
+        let size: usize = fields.iter().map(|(name, value)| name.len() + value.len() + 32).sum();
+        let mut stream = self.new_stream(if end_stream { Recv::Closed } else { Recv::Open });
+        stream.fields = fields;
+        if size > MAX_HEADER_LIST_SIZE {
+            if !end_stream {
+                stream.recv = Recv::Discard;
+            }
+            self.streams.insert(id, stream);
+            self.dispatch(id, Some(431));
+        } else {
+            self.streams.insert(id, stream);
+            if end_stream {
+                self.dispatch(id, None);
+            }
+        }
+        Ok(())
+    }
+
+    // Hands a stream's request to a handler thread
+    fn dispatch(&mut self, id: u32, error: Option<u16>) {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return;
+        };
+        let fields = mem::take(&mut stream.fields);
+        let body = mem::take(&mut stream.body);
+        let trailers = mem::take(&mut stream.trailers);
+        match to_request(fields, body, trailers, error) {
+            Some(request) => {
+                let head_request = request.method == "HEAD";
+                let respond = self.respond;
+                self.spawn(id, head_request, Box::new(move || respond(request)));
+            }
+            None => self.reset(id, PROTOCOL_ERROR),
+        }
+    }
+
+    fn spawn(&mut self, id: u32, head_request: bool, respond: Box<dyn FnOnce() -> Response + Send + 'env>) {
+        let events = self.events.clone();
+        let name = thread::current().name().map(|name| format!("{}-h2", name));
+        let mut builder = thread::Builder::new();
+        if let Some(name) = name {
+            builder = builder.name(name);
+        }
+        let spawned = builder.spawn_scoped(self.scope, move || {
+            let response = respond();
+            let reader = if head_request || !response.has_content() {
+                None
+            } else {
+                response.body_reader()
+            };
+            let Some(mut reader) = reader else {
+                let _ = events.send(Event::Response(id, response, head_request, None));
+                return;
+            };
+            let (chunks, queue) = mpsc::sync_channel(QUEUED_CHUNKS);
+            if events.send(Event::Response(id, response, false, Some(queue))).is_err() {
+                return;
+            }
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let chunk = match reader.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        Ok(chunk)
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                if chunks.send(chunk).is_err() || events.send(Event::Body).is_err() || failed {
+                    return;
+                }
+            }
+            drop(chunks);
+            let _ = events.send(Event::Body);
+        });
+        if spawned.is_err() {
+            self.reset(id, REFUSED_STREAM);
+        }
+    }
+
+    fn on_response(
+        &mut self,
+        id: u32,
+        response: Response,
+        head_request: bool,
+        source: Option<Receiver<io::Result<Vec<u8>>>>,
+    ) {
+        // Reset by the client in the meantime
+        if !self.streams.contains_key(&id) {
+            return;
+        }
+        let block = hpack::encode(response.status_code, &response.h2_fields());
+        let done = source.is_none()
+            && (head_request || !response.has_content() || response.body.is_empty());
+        let mut fragments = block.chunks(self.frame_size).peekable();
+        let mut kind = HEADERS;
+        while let Some(fragment) = fragments.next() {
+            let mut flags = 0;
+            if kind == HEADERS && done {
+                flags |= END_STREAM;
+            }
+            if fragments.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.writer.frame(kind, flags, id, fragment);
+            kind = CONTINUATION;
+        }
+        if done {
+            self.finish(id);
+            return;
+        }
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.out = Some(Outgoing {
+                pending: if source.is_some() { Vec::new() } else { response.body },
+                sent: 0,
+                source,
+            });
+        }
+        self.flush();
+    }
+
+    // Sends what response bodies the windows allow, oldest stream first
+    fn flush(&mut self) {
+        let mut ids: Vec<u32> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| stream.out.is_some())
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        for id in ids {
+            while let Some(stream) = self.streams.get_mut(&id) {
+                let Some(out) = stream.out.as_mut() else {
+                    break;
+                };
+                if out.sent == out.pending.len() {
+                    let Some(source) = &out.source else {
+                        // A streamed body that ended on a chunk boundary
+                        self.writer.frame(DATA, END_STREAM, id, &[]);
+                        self.finish(id);
+                        break;
+                    };
+                    match source.try_recv() {
+                        Ok(Ok(chunk)) => {
+                            out.pending = chunk;
+                            out.sent = 0;
+                        }
+                        Ok(Err(e)) => {
+                            eprintln!("Error streaming response body: {}", e);
+                            self.reset(id, INTERNAL_ERROR);
+                            break;
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => out.source = None,
+                    }
+                    continue;
+                }
+                let window = self.send_window.min(stream.send_window);
+                if window <= 0 {
+                    break;
+                }
+                let n = (out.pending.len() - out.sent)
+                    .min(window as usize)
+                    .min(self.frame_size);
+                let last = out.sent + n == out.pending.len() && out.source.is_none();
+                let flags = if last { END_STREAM } else { 0 };
+                self.writer.frame(DATA, flags, id, &out.pending[out.sent..out.sent + n]);
+                out.sent += n;
+                stream.send_window -= n as i64;
+                self.send_window -= n as i64;
+                if last {
+                    self.finish(id);
+                    break;
+                }
+            }
+        }
+    }
+
+    // The response has been sent in full
+    fn finish(&mut self, id: u32) {
+        if let Some(stream) = self.streams.remove(&id)
+            && stream.recv != Recv::Closed
+        {
+            // Answered before the request was all in; the rest isn't wanted
+            self.writer.reset(id, NO_ERROR);
+        }
+    }
+
+    fn reset(&mut self, id: u32, code: u32) {
+        self.streams.remove(&id);
+        self.writer.reset(id, code);
+    }
+}
+
+// The payload less any padding, and the priority fields of HEADERS
+fn unpad(frame: &Frame, headers: bool) -> Result<&[u8], u32> {
+    let mut data = &frame.payload[..];
+    let mut pad = 0;
+    if frame.flags & PADDED != 0 {
+        let Some((&len, rest)) = data.split_first() else {
+            return Err(FRAME_SIZE_ERROR);
+        };
+        pad = len as usize;
+        data = rest;
+    }
+    if headers && frame.flags & PRIORITY_FLAG != 0 {
+        if data.len() < 5 {
+            return Err(FRAME_SIZE_ERROR);
+        }
+        data = &data[5..];
+    }
+    if pad > data.len() {
+        return Err(PROTOCOL_ERROR);
+    }
+    Ok(&data[..data.len() - pad])
+}
+
+// Checks the rules of RFC 9113 section 8.2 and 8.3; None on a malformed
+// request
+fn to_request(
+    fields: Vec<(String, String)>,
+    body: Vec<u8>,
+    trailers: HashMap<String, String>,
+    error: Option<u16>,
+) -> Option<H2Request> {
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut headers = Vec::new();
+    let mut cookies = Vec::new();
+    for (name, value) in fields {
+        if value.bytes().any(|b| matches!(b, b'\0' | b'\r' | b'\n'))
+            || value.starts_with([' ', '\t'])
+            || value.ends_with([' ', '\t'])
+        {
+            return None;
+        }
+        if let Some(pseudo) = name.strip_prefix(':') {
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return None,
+            };
+            // Pseudo-headers come first, once each
+            if !headers.is_empty() || !cookies.is_empty() || slot.is_some() {
+                return None;
+            }
+            *slot = Some(value);
+            continue;
+        }
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && !b.is_ascii_uppercase() && !b"\"(),/:;<=>?@[\\]{}".contains(&b))
+        {
+            return None;
+        }
+        match name.as_str() {
+            "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade" => {
+                return None;
+            }
+            "te" if value != "trailers" => return None,
+            "cookie" => cookies.push(value),
+            _ => headers.push((name, value)),
+        }
+    }
+
+    let method = method?;
+    let target = if method == "CONNECT" {
+        if scheme.is_some() || path.is_some() {
+            return None;
+        }
+        authority.clone()?
+    } else {
+        scheme?;
+        path?
+    };
+    if target.is_empty() || target.bytes().any(|b| b <= b' ' || b == 0x7f) {
+        return None;
+    }
+    if !cookies.is_empty() {
+        headers.push(("cookie".to_string(), cookies.join("; ")));
+    }
+    if let Some(authority) = authority
+        && !headers.iter().any(|(name, _)| name == "host")
+    {
+        headers.insert(0, ("host".to_string(), authority));
+    }
+    if error.is_none()
+        && let Some((_, length)) = headers.iter().find(|(name, _)| name == "content-length")
+        && length.parse::<usize>().ok() != Some(body.len())
+    {
+        return None;
+    }
+    Some(H2Request {
+        method,
+        target,
+        headers,
+        body,
+        trailers,
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn request(list: &[(&str, &str)]) -> Option<H2Request> {
+        to_request(fields(list), Vec::new(), HashMap::new(), None)
+    }
+
+    #[test]
+    fn maps_pseudo_headers() {
+        let req = request(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":authority", "example.com"),
+            (":path", "/a?b=c"),
+            ("cookie", "a=1"),
+            ("accept", "*/*"),
+            ("cookie", "b=2"),
+        ])
+        .unwrap();
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.target, "/a?b=c");
+        assert_eq!(
+            req.headers,
+            fields(&[("host", "example.com"), ("accept", "*/*"), ("cookie", "a=1; b=2")])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let base = [(":method", "GET"), (":scheme", "http"), (":path", "/")];
+        assert!(request(&base).is_some());
+        assert!(request(&base[..2]).is_none());
+        assert!(request(&[base[0], base[1], base[2], (":path", "/x")]).is_none());
+        assert!(request(&[base[0], base[1], ("accept", "*/*"), base[2]]).is_none());
+        assert!(request(&[base[0], base[1], base[2], (":status", "200")]).is_none());
+        assert!(request(&[base[0], base[1], base[2], ("Accept", "*/*")]).is_none());
+        assert!(request(&[base[0], base[1], base[2], ("connection", "close")]).is_none());
+        assert!(request(&[base[0], base[1], base[2], ("te", "gzip")]).is_none());
+        assert!(request(&[base[0], base[1], base[2], ("te", "trailers")]).is_some());
+        assert!(request(&[base[0], base[1], base[2], ("x", "a\r\nb: c")]).is_none());
+        assert!(request(&[base[0], base[1], (":path", "/a b")]).is_none());
+    }
+
+    #[test]
+    fn content_length_must_match_the_body() {
+        let list = fields(&[
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", "/"),
+            ("content-length", "3"),
+        ]);
+        assert!(to_request(list.clone(), b"abc".to_vec(), HashMap::new(), None).is_some());
+        assert!(to_request(list.clone(), b"ab".to_vec(), HashMap::new(), None).is_none());
+        assert!(to_request(list, Vec::new(), HashMap::new(), Some(413)).is_some());
+    }
+
+    #[test]
+    fn strips_padding_and_priority() {
+        let frame = Frame {
+            kind: HEADERS,
+            flags: PADDED | PRIORITY_FLAG,
+            stream: 1,
+            payload: [&[2][..], &[0, 0, 0, 0, 16], b"abc", &[0, 0]].concat(),
+        };
+        assert_eq!(unpad(&frame, true).unwrap(), b"abc");
+        let frame = Frame {
+            kind: DATA,
+            flags: PADDED,
+            stream: 1,
+            payload: vec![4, b'a', 0, 0],
+        };
+        assert_eq!(unpad(&frame, false), Err(PROTOCOL_ERROR));
+    }
+}
@@ -0,0 +1,124 @@
+// Response headers in the order they were added. A name may appear more
+// than once (Set-Cookie, Link, Vary), each value sent on its own line.
+// Names are compared case-insensitively but sent as given. CR, LF and NUL
+// are dropped from names and values, so a value taken from a request can't
+// end its line and add headers of its own.
+
+use std::slice;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces every earlier value of `name`, keeping the place of the first
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let (name, value) = (clean(name.into()), clean(value.into()));
+        let Some(first) = self.entries.iter().position(|(k, _)| k.eq_ignore_ascii_case(&name)) else {
+            self.entries.push((name, value));
+            return;
+        };
+        // Nothing before `first` matches, so it keeps its index
+        let mut index = 0;
+        self.entries.retain(|(k, _)| {
+            let keep = index == first || !k.eq_ignore_ascii_case(&name);
+            index += 1;
+            keep
+        });
+        self.entries[first] = (name, value);
+    }
+
+    // Adds a value, keeping the ones already there
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((clean(name.into()), clean(value.into())));
+    }
+
+    // The first value of `name`
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    // Every value of `name`, in order
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // Drops every value of `name`, giving back the first
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let first = self.get(name).cloned();
+        self.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        first
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&String, &String) -> bool) {
+        self.entries.retain(|(k, v)| keep(k, v));
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            entries: self.entries.iter(),
+        }
+    }
+
+    // Number of lines, counting each value of a repeated name
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn clean(mut text: String) -> String {
+    if text.contains(['\r', '\n', '\0']) {
+        text.retain(|c| !matches!(c, '\r' | '\n' | '\0'));
+    }
+    text
+}
+
+pub struct Iter<'a> {
+    entries: slice::Iter<'a, (String, String)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|(k, v)| (k, v))
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = (&'a String, &'a String);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
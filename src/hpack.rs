@@ -0,0 +1,419 @@
+// HPACK (RFC 7541), the header compression of HTTP/2. Requests are decoded
+// in full, Huffman strings and the dynamic table included. Responses are
+// encoded as literals that never enter the client's dynamic table, so the
+// encoder keeps no state.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::LazyLock;
+
+// The default SETTINGS_HEADER_TABLE_SIZE, which is all we allow the client
+const TABLE_SIZE: usize = 4096;
+
+// Appendix A
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// Code length of each symbol in Appendix B, 256 being EOS. The code is
+// canonical, so the codes themselves follow from the lengths.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, //
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28, //
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, //
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, //
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, //
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, //
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5, //
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, //
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23, //
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, //
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, //
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23, //
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, //
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, //
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23, //
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, //
+    30,
+];
+
+const EOS: u16 = 256;
+const MAX_CODE_LEN: usize = 30;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct HpackError;
+
+impl fmt::Display for HpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid HPACK header block")
+    }
+}
+
+// Canonical decoding tables: for each length, the first code and where
+// its symbols start in `symbols`
+struct Huffman {
+    first_code: [u32; MAX_CODE_LEN + 1],
+    first_index: [usize; MAX_CODE_LEN + 1],
+    count: [u32; MAX_CODE_LEN + 1],
+    symbols: Vec<u16>,
+}
+
+static HUFFMAN: LazyLock<Huffman> = LazyLock::new(|| {
+    let mut symbols: Vec<u16> = (0..=EOS).collect();
+    symbols.sort_by_key(|&s| (HUFFMAN_LENGTHS[s as usize], s));
+    let mut count = [0u32; MAX_CODE_LEN + 1];
+    for &len in &HUFFMAN_LENGTHS {
+        count[len as usize] += 1;
+    }
+    let mut first_code = [0u32; MAX_CODE_LEN + 1];
+    let mut first_index = [0usize; MAX_CODE_LEN + 1];
+    let (mut code, mut index) = (0u32, 0usize);
+    for len in 1..=MAX_CODE_LEN {
+        code <<= 1;
+        first_code[len] = code;
+        first_index[len] = index;
+        code += count[len];
+        index += count[len] as usize;
+    }
+    Huffman {
+        first_code,
+        first_index,
+        count,
+        symbols,
+    }
+});
+
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let table = &*HUFFMAN;
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0usize);
+    for &byte in data {
+        for shift in (0..8).rev() {
+            code = (code << 1) | ((byte >> shift) & 1) as u32;
+            len += 1;
+            let offset = code.wrapping_sub(table.first_code[len]);
+            if offset < table.count[len] {
+                let symbol = table.symbols[table.first_index[len] + offset as usize];
+                if symbol == EOS {
+                    return Err(HpackError);
+                }
+                out.push(symbol as u8);
+                code = 0;
+                len = 0;
+            } else if len == MAX_CODE_LEN {
+                return Err(HpackError);
+            }
+        }
+    }
+    // Padding is the start of EOS (all ones) and shorter than a byte
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(HpackError);
+    }
+    Ok(out)
+}
+
+// Section 5.1. `prefix` is the number of low bits of the first byte used.
+fn decode_int(data: &[u8], pos: &mut usize, prefix: u8) -> Result<usize, HpackError> {
+    let max = (1usize << prefix) - 1;
+    let first = *data.get(*pos).ok_or(HpackError)? as usize & max;
+    *pos += 1;
+    if first < max {
+        return Ok(first);
+    }
+    let mut value = max;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).ok_or(HpackError)?;
+        *pos += 1;
+        // Nothing legitimate needs more than 28 bits
+        if shift > 21 {
+            return Err(HpackError);
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_int(out: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Section 5.2
+fn decode_string(data: &[u8], pos: &mut usize) -> Result<Vec<u8>, HpackError> {
+    let huffman = *data.get(*pos).ok_or(HpackError)? & 0x80 != 0;
+    let len = decode_int(data, pos, 7)?;
+    let end = pos.checked_add(len).filter(|&end| end <= data.len()).ok_or(HpackError)?;
+    let raw = &data[*pos..end];
+    *pos = end;
+    if huffman { huffman_decode(raw) } else { Ok(raw.to_vec()) }
+}
+
+// Per-connection decoding state: the dynamic table the client's encoder
+// builds up across header blocks
+pub(crate) struct Decoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: TABLE_SIZE,
+        }
+    }
+}
+
+impl Decoder {
+    // A complete header block (HEADERS plus any CONTINUATION fragments) as
+    // (name, value) pairs in order. Values that aren't UTF-8 are decoded
+    // lossily. An error leaves the table unusable, so the connection has
+    // to be closed.
+    pub(crate) fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < block.len() {
+            let byte = block[pos];
+            if byte & 0x80 != 0 {
+                // Indexed field
+                let index = decode_int(block, &mut pos, 7)?;
+                fields.push(self.entry(index)?);
+            } else if byte & 0xe0 == 0x20 {
+                // Table size update, only allowed before the first field
+                let size = decode_int(block, &mut pos, 5)?;
+                if size > TABLE_SIZE || !fields.is_empty() {
+                    return Err(HpackError);
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Literal; with incremental indexing it joins the table
+                let (prefix, indexed) = if byte & 0x40 != 0 { (6, true) } else { (4, false) };
+                let index = decode_int(block, &mut pos, prefix)?;
+                let name = if index == 0 {
+                    String::from_utf8_lossy(&decode_string(block, &mut pos)?).into_owned()
+                } else {
+                    self.entry(index)?.0
+                };
+                let value = String::from_utf8_lossy(&decode_string(block, &mut pos)?).into_owned();
+                if indexed {
+                    self.insert(name.clone(), value.clone());
+                }
+                fields.push((name, value));
+            }
+        }
+        Ok(fields)
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), HpackError> {
+        match index {
+            0 => Err(HpackError),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            _ => self.table.get(index - 62).cloned().ok_or(HpackError),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + 32;
+        self.evict(size);
+        // An entry larger than the table just empties it
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    // Drop the oldest entries until `room` more bytes fit
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + 32;
+        }
+    }
+}
+
+// A response header block: :status first, then `fields` with lower-case
+// names, each a literal never indexed by the client's decoder
+pub(crate) fn encode(status: u16, fields: &[(String, String)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let status = status.to_string();
+    match STATIC_TABLE.iter().position(|&(name, value)| name == ":status" && value == status) {
+        Some(index) => encode_int(&mut out, 0x80, 7, index + 1),
+        None => encode_literal(&mut out, 8, ":status", &status),
+    }
+    for (name, value) in fields {
+        let index = STATIC_TABLE.iter().position(|&(n, _)| n == name).map_or(0, |i| i + 1);
+        encode_literal(&mut out, index, name, value);
+    }
+    out
+}
+
+// Literal without indexing, naming a static entry when `index` isn't 0
+fn encode_literal(out: &mut Vec<u8>, index: usize, name: &str, value: &str) {
+    encode_int(out, 0x00, 4, index);
+    if index == 0 {
+        encode_int(out, 0x00, 7, name.len());
+        out.extend(name.as_bytes());
+    }
+    encode_int(out, 0x00, 7, value.len());
+    out.extend(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn pairs(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    // RFC 7541 C.3: requests without Huffman coding, sharing a table
+    #[test]
+    fn decodes_literal_requests() {
+        let mut decoder = Decoder::default();
+        let first = decoder.decode(&hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d")).unwrap();
+        assert_eq!(
+            first,
+            pairs(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")])
+        );
+        let second = decoder.decode(&hex("8286 84be 5808 6e6f 2d63 6163 6865")).unwrap();
+        assert_eq!(
+            second,
+            pairs(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+        assert_eq!(decoder.size, 110);
+    }
+
+    // RFC 7541 C.4: the same requests with Huffman coding
+    #[test]
+    fn decodes_huffman_requests() {
+        let mut decoder = Decoder::default();
+        decoder.decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff")).unwrap();
+        decoder.decode(&hex("8286 84be 5886 a8eb 1064 9cbf")).unwrap();
+        let third = decoder
+            .decode(&hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"))
+            .unwrap();
+        assert_eq!(
+            third,
+            pairs(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_bad_padding_and_indexes() {
+        // "a" (00011) padded with zeros instead of ones
+        assert_eq!(huffman_decode(&[0x18]), Err(HpackError));
+        // A whole byte of padding
+        assert_eq!(huffman_decode(&[0x1f, 0xff]), Err(HpackError));
+        assert_eq!(Decoder::default().decode(&[0x80]), Err(HpackError));
+        assert_eq!(Decoder::default().decode(&[0xbe]), Err(HpackError));
+    }
+
+    #[test]
+    fn encoded_responses_decode() {
+        let fields = pairs(&[("content-type", "text/plain"), ("x-custom", "a".repeat(200).as_str())]);
+        let block = encode(201, &fields);
+        let mut expected = pairs(&[(":status", "201")]);
+        expected.extend(fields);
+        assert_eq!(Decoder::default().decode(&block).unwrap(), expected);
+        assert_eq!(encode(200, &[]), [0x88]);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::request::Request;
+use crate::response::Response;
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const HEADER: &str = "idempotency-key";
+
+enum Slot {
+    // First request is still being handled
+    InFlight,
+    Done { fingerprint: u64, stored: Instant, response: Response },
+}
+
+// Key is (Idempotency-Key, "METHOD path")
+type Slots = HashMap<(String, String), Slot>;
+
+#[derive(Clone)]
+pub(crate) struct IdempotencyStore {
+    ttl: Duration,
+    slots: Arc<Mutex<Slots>>,
+}
+
+// Releases the in-flight slot if the handler never completed (e.g. it panicked)
+struct InFlightGuard<'a> {
+    store: &'a IdempotencyStore,
+    key: Option<(String, String)>,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.store.slots.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+impl IdempotencyStore {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            slots: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Run `handle` at most once per key and route; retries get the stored response
+    pub(crate) fn run(&self, req: &mut Request, handle: impl FnOnce(&mut Request) -> Response) -> Response {
+        let Some(idempotency_key) = req.header(HEADER) else {
+            return handle(req);
+        };
+        if !matches!(req.method.as_str(), "POST" | "PUT" | "PATCH" | "DELETE") {
+            return handle(req);
+        }
+
+        let key = (idempotency_key.clone(), format!("{} {}", req.method, req.path));
+        let fingerprint = fingerprint(&req.body);
+
+        {
+            let mut slots = self.slots.lock().unwrap();
+            let ttl = self.ttl;
+            slots.retain(|_, slot| match slot {
+                Slot::InFlight => true,
+                Slot::Done { stored, .. } => stored.elapsed() < ttl,
+            });
+
+            match slots.get(&key) {
+                Some(Slot::InFlight) => {
+                    return plain(409, "A request with this Idempotency-Key is still being processed");
+                }
+                Some(Slot::Done { fingerprint: stored, .. }) if *stored != fingerprint => {
+                    return plain(422, "Idempotency-Key was already used with a different payload");
+                }
+                Some(Slot::Done { response, .. }) => {
+                    return response.clone().with_header("Idempotent-Replayed", "true");
+                }
+                None => {
+                    slots.insert(key.clone(), Slot::InFlight);
+                }
+            }
+        }
+
+        let mut guard = InFlightGuard {
+            store: self,
+            key: Some(key),
+        };
+        let response = handle(req);
+
+        // Server errors are not stored so the client can retry for real, and
+        // neither are streamed bodies, which can only be sent once
+        if let Some(key) = guard.key.take() {
+            let mut slots = self.slots.lock().unwrap();
+            if response.status_code >= 500 || response.is_streamed() {
+                slots.remove(&key);
+            } else {
+                slots.insert(
+                    key,
+                    Slot::Done {
+                        fingerprint,
+                        stored: Instant::now(),
+                        response: response.clone(),
+                    },
+                );
+            }
+        }
+        response
+    }
+}
+
+fn fingerprint(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn plain(code: u16, message: &str) -> Response {
+    Response::new(code, message.as_bytes().to_vec(), "text/plain")
+}
@@ -0,0 +1,92 @@
+// JSON request bodies, behind the `json` feature
+
+use crate::error::error_response;
+use crate::mime::Mime;
+use crate::request::Request;
+use crate::response::{Response, ResponseBuilder};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum JsonError {
+    // Content-Type was set to something other than JSON
+    UnsupportedMediaType(String),
+    // The body isn't valid JSON or doesn't fit the target type
+    Invalid(serde_json::Error),
+}
+
+impl JsonError {
+    // 415 when the body isn't declared as JSON, 400 when it doesn't
+    // deserialize into the wanted type
+    pub fn status(&self) -> u16 {
+        match self {
+            JsonError::UnsupportedMediaType(_) => 415,
+            JsonError::Invalid(_) => 400,
+        }
+    }
+
+}
+
+error_response!(JsonError);
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::UnsupportedMediaType(content_type) => {
+                write!(f, "expected a JSON body, got Content-Type {}", content_type)
+            }
+            JsonError::Invalid(e) => write!(f, "invalid JSON body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JsonError::UnsupportedMediaType(_) => None,
+            JsonError::Invalid(e) => Some(e),
+        }
+    }
+}
+
+impl Request {
+    // Deserialize the body, e.g. let item: NewItem = req.json()?. A missing
+    // Content-Type is accepted; any other than application/json or
+    // application/*+json is not.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        if let Some(content_type) = self.header("content-type") {
+            let is_json = Mime::parse(content_type).is_some_and(|mime| {
+                mime.main_type() == "application" && (mime.subtype() == "json" || mime.suffix() == Some("json"))
+            });
+            if !is_json {
+                return Err(JsonError::UnsupportedMediaType(content_type.clone()));
+            }
+        }
+        serde_json::from_slice(&self.body).map_err(JsonError::Invalid)
+    }
+}
+
+impl Response {
+    // 200 with `value` as application/json; use Response::builder() for
+    // another status. A value that can't be serialized gives a 500.
+    pub fn json<T: Serialize>(value: &T) -> Response {
+        Response::builder().json(value).build()
+    }
+}
+
+impl ResponseBuilder {
+    // `value` serialized as the body, with application/json. If it can't
+    // be serialized (e.g. a map with non-string keys) the response becomes
+    // a 500 instead.
+    pub fn json<T: Serialize>(self, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => self.content_type("application/json").body(body),
+            Err(e) => self
+                .status(500)
+                .content_type("text/plain")
+                .body(format!("failed to serialize JSON response: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// Language ranges as found in Accept-Language
+
+// Ranges with their q-values (1 when absent), lowercased
+pub(crate) fn parse_accept_language(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim().to_ascii_lowercase();
+            if range.is_empty() {
+                return None;
+            }
+            let q = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .and_then(|(_, q)| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            Some((range, q))
+        })
+        .collect()
+}
+
+// q-value the best matching range gives `tag`; 0 if none matches. An exact
+// match beats a range that is a prefix of the tag ("en" for "en-GB"),
+// which beats a range the tag is a prefix of ("en-US" for "en", so a
+// client asking only for en-US still gets plain en), which beats "*".
+pub(crate) fn quality(ranges: &[(String, f32)], tag: &str) -> f32 {
+    let tag = tag.to_ascii_lowercase();
+    let is_prefix = |short: &str, long: &str| long.starts_with(short) && long.as_bytes().get(short.len()) == Some(&b'-');
+    ranges
+        .iter()
+        .filter_map(|(range, q)| {
+            let rank = if *range == tag {
+                3
+            } else if is_prefix(range, &tag) {
+                2
+            } else if is_prefix(&tag, range) {
+                1
+            } else if range == "*" {
+                0
+            } else {
+                return None;
+            };
+            Some((rank, *q))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .map_or(0.0, |(_, q)| q)
+}
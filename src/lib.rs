@@ -1,11 +1,78 @@
+mod archive;
+mod assets;
+mod base64;
+mod body;
+mod chunked;
+mod compress;
+mod concurrency;
+mod cookie;
+mod cors;
+mod docs;
+mod error;
+mod extensions;
+mod form;
+mod forwarded;
+mod h2c;
+mod headers;
+mod hpack;
+mod http_date;
+mod idempotency;
+#[cfg(feature = "json")]
+mod json;
+mod language;
+mod metrics;
+mod mime;
+mod param;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod proxy;
+mod regex;
 mod request;
 mod response;
 mod router;
+mod seo;
 mod server;
+mod single_flight;
+mod socket;
+mod sse;
+mod static_files;
 mod template;
+mod text;
+mod urls;
+pub mod validate;
+mod well_known;
+mod writable;
+#[cfg(feature = "xml")]
+mod xml;
 
-pub use request::Request;
-pub use response::Response;
-pub use router::{Router, HandlerFn};
+pub use body::BodyReader;
+pub use concurrency::{check_if_match, content_etag, not_modified, version_etag};
+pub use cookie::{Cookie, SameSite};
+pub use cors::Cors;
+pub use error::Error;
+pub use extensions::Extensions;
+pub use form::FormValue;
+pub use headers::Headers;
+pub use http_date::{http_date, parse_http_date};
+#[cfg(feature = "json")]
+pub use json::JsonError;
+pub use metrics::{Metrics, WorkerStats};
+pub use mime::Mime;
+pub use param::ParamError;
+#[cfg(feature = "protobuf")]
+pub use protobuf::ProtobufError;
+pub use proxy::Proxy;
+pub use regex::{Regex, RegexError};
+pub use request::{Request, RequestBuilder};
+pub use response::{IntoResponse, Response, ResponseBuilder};
+pub use router::{HandlerFn, Params, PathCase, Route, RouteGroup, RouteGuard, RouteHandle, RouteInfo, RouteMatch, RouteMiddleware, Router, TrailingSlash};
+pub use seo::{Robots, Sitemap, SitemapProvider};
 pub use server::SimpleHttpServer;
-pub use template::TemplateEngine;
\ No newline at end of file
+pub use socket::{AcceptHook, SocketOptions};
+pub use sse::{SseClosed, SseEvent, SseSender, SseStream};
+pub use template::TemplateEngine;
+pub use text::TextError;
+pub use well_known::AcmeChallenges;
+pub use writable::{AuthorizeFn, WritableMount};
+#[cfg(feature = "xml")]
+pub use xml::XmlError;
\ No newline at end of file
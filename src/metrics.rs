@@ -0,0 +1,177 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Counters owned by one connection thread
+pub(crate) struct WorkerCounters {
+    name: String,
+    requests: AtomicU64,
+    // Time spent on requests that have finished
+    busy_micros: AtomicU64,
+    // Start times of the requests in progress; more than one on an h2c
+    // connection
+    running: Mutex<Vec<Instant>>,
+}
+
+impl WorkerCounters {
+    // Finished requests plus the time so far of those in progress
+    fn busy(&self) -> Duration {
+        let running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        let done = Duration::from_micros(self.busy_micros.load(Ordering::Relaxed));
+        running.iter().fold(done, |busy, started| busy + started.elapsed())
+    }
+}
+
+// Registration of a running thread; its counts move to the finished totals
+// when it is dropped, even if the thread panicked
+pub(crate) struct WorkerHandle {
+    metrics: Metrics,
+    counters: Arc<WorkerCounters>,
+}
+
+impl WorkerHandle {
+    // Counts a request as it starts; its time is added to the busy total
+    // when the returned guard is dropped
+    pub(crate) fn start_request(&self) -> RequestTimer<'_> {
+        let started = Instant::now();
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(started);
+        RequestTimer {
+            counters: &self.counters,
+            started,
+        }
+    }
+}
+
+pub(crate) struct RequestTimer<'a> {
+    counters: &'a WorkerCounters,
+    started: Instant,
+}
+
+impl Drop for RequestTimer<'_> {
+    fn drop(&mut self) {
+        let mut running = self.counters.running.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(pos) = running.iter().position(|started| *started == self.started) {
+            running.swap_remove(pos);
+        }
+        // Moved while holding the lock, so busy() never sees it twice
+        self.counters
+            .busy_micros
+            .fetch_add(self.started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        // Under the lock, so total_requests never counts a thread twice or
+        // not at all
+        let mut workers = self.metrics.workers.lock().unwrap_or_else(|e| e.into_inner());
+        self.metrics
+            .finished_requests
+            .fetch_add(self.counters.requests.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.metrics
+            .finished_busy_micros
+            .fetch_add(self.counters.busy_micros.load(Ordering::Relaxed), Ordering::Relaxed);
+        workers.retain(|w| !Arc::ptr_eq(w, &self.counters));
+    }
+}
+
+// Snapshot of one worker's activity
+#[derive(Clone, Debug)]
+pub struct WorkerStats {
+    pub name: String,
+    pub requests: u64,
+    pub busy: Duration,
+}
+
+// Live server statistics; clones share the same counters
+#[derive(Clone, Default)]
+pub struct Metrics {
+    workers: Arc<Mutex<Vec<Arc<WorkerCounters>>>>,
+    finished_requests: Arc<AtomicU64>,
+    finished_busy_micros: Arc<AtomicU64>,
+}
+
+impl Metrics {
+    pub(crate) fn register_worker(&self, name: &str) -> WorkerHandle {
+        let counters = Arc::new(WorkerCounters {
+            name: name.to_string(),
+            requests: AtomicU64::new(0),
+            busy_micros: AtomicU64::new(0),
+            running: Mutex::new(Vec::new()),
+        });
+        self.workers.lock().unwrap().push(Arc::clone(&counters));
+        WorkerHandle {
+            metrics: self.clone(),
+            counters,
+        }
+    }
+
+    // The threads serving a connection right now
+    pub fn workers(&self) -> Vec<WorkerStats> {
+        self.workers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|w| WorkerStats {
+                name: w.name.clone(),
+                requests: w.requests.load(Ordering::Relaxed),
+                busy: w.busy(),
+            })
+            .collect()
+    }
+
+    // Requests started since start, by live and finished threads
+    pub fn total_requests(&self) -> u64 {
+        let workers = self.workers.lock().unwrap();
+        let live: u64 = workers.iter().map(|w| w.requests.load(Ordering::Relaxed)).sum();
+        live + self.finished_requests.load(Ordering::Relaxed)
+    }
+
+    // Time spent on requests since start, those in progress included
+    pub fn total_busy(&self) -> Duration {
+        let workers = self.workers.lock().unwrap();
+        let finished = Duration::from_micros(self.finished_busy_micros.load(Ordering::Relaxed));
+        workers.iter().fold(finished, |busy, w| busy + w.busy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn counts_requests_while_they_run() {
+        let metrics = Metrics::default();
+        let worker = metrics.register_worker("rake-worker-1");
+        let timer = worker.start_request();
+        thread::sleep(Duration::from_millis(20));
+        let live = &metrics.workers()[0];
+        assert_eq!(live.requests, 1);
+        assert!(live.busy >= Duration::from_millis(20));
+        drop(timer);
+        assert_eq!(metrics.total_requests(), 1);
+    }
+
+    #[test]
+    fn finished_workers_keep_their_busy_time() {
+        let metrics = Metrics::default();
+        let worker = metrics.register_worker("rake-worker-1");
+        drop(worker.start_request());
+        thread::sleep(Duration::from_millis(5));
+        let timer = worker.start_request();
+        thread::sleep(Duration::from_millis(20));
+        drop(timer);
+        let busy = metrics.total_busy();
+        drop(worker);
+        assert!(metrics.workers().is_empty());
+        assert_eq!(metrics.total_requests(), 2);
+        assert_eq!(metrics.total_busy(), busy);
+        assert!(busy >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,124 @@
+// Media types as found in Content-Type and Accept
+
+use std::fmt;
+
+// A parsed media type such as `text/html; charset=utf-8`. Type, subtype and
+// parameter names are lowercased; parameter values keep their case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mime {
+    essence: String,
+    slash: usize,
+    params: Vec<(String, String)>,
+}
+
+impl Mime {
+    // None unless `value` has the `type/subtype` shape
+    pub fn parse(value: &str) -> Option<Mime> {
+        let mut parts = value.split(';');
+        let essence = parts.next()?.trim().to_ascii_lowercase();
+        let slash = essence.find('/')?;
+        let valid = |s: &str| !s.is_empty() && s.bytes().all(is_token_byte);
+        if !valid(&essence[..slash]) || !valid(&essence[slash + 1..]) {
+            return None;
+        }
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.trim().to_ascii_lowercase(), value.to_string())
+            })
+            .collect();
+        Some(Mime { essence, slash, params })
+    }
+
+    // `type/subtype` without parameters
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    pub fn main_type(&self) -> &str {
+        &self.essence[..self.slash]
+    }
+
+    pub fn subtype(&self) -> &str {
+        &self.essence[self.slash + 1..]
+    }
+
+    // Structured syntax suffix, e.g. "json" for application/ld+json
+    pub fn suffix(&self) -> Option<&str> {
+        self.subtype().rsplit_once('+').map(|(_, suffix)| suffix)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    // Whether this (possibly wildcard) range covers `other`, e.g. text/*
+    // covers text/html
+    pub(crate) fn covers(&self, other: &Mime) -> bool {
+        match (self.main_type(), self.subtype()) {
+            ("*", "*") => true,
+            (main, "*") => main == other.main_type(),
+            _ => self.essence == other.essence,
+        }
+    }
+
+    fn specificity(&self) -> u8 {
+        match (self.main_type(), self.subtype()) {
+            ("*", _) => 0,
+            (_, "*") => 1,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Mime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.essence)?;
+        for (name, value) in &self.params {
+            write!(f, "; {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$&-^_.+*".contains(&b)
+}
+
+// Media ranges of an Accept header with their q-values (1 when absent)
+pub(crate) fn parse_accept(header: &str) -> Vec<(Mime, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let range = Mime::parse(item)?;
+            let q = range
+                .param("q")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            Some((range, q))
+        })
+        .collect()
+}
+
+// q-value the most specific matching range gives `media_type`; 0 if none
+// matches
+pub(crate) fn quality(ranges: &[(Mime, f32)], media_type: &Mime) -> f32 {
+    ranges
+        .iter()
+        .filter(|(range, _)| range.covers(media_type))
+        .max_by_key(|(range, _)| range.specificity())
+        .map_or(0.0, |(_, q)| *q)
+}
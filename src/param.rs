@@ -0,0 +1,52 @@
+// Typed access to query parameters
+
+use crate::error::error_response;
+use crate::request::Request;
+
+use std::fmt;
+use std::str::FromStr;
+
+// A query parameter that is present but doesn't parse as the wanted type
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParamError {
+    pub name: String,
+    pub value: String,
+    // The parser's own message, e.g. "invalid digit found in string"
+    pub reason: String,
+}
+
+impl ParamError {
+    // Always 400: the parameter was sent, just not in a form that parses
+    pub fn status(&self) -> u16 {
+        400
+    }
+}
+
+error_response!(ParamError);
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid query parameter {}={:?}: {}", self.name, self.value, self.reason)
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+impl Request {
+    // A query parameter parsed as T, e.g. req.param_as::<u32>("page"). Ok(None)
+    // if it is absent, so a default can be applied with unwrap_or.
+    pub fn param_as<T>(&self, key: &str) -> Result<Option<T>, ParamError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let Some(value) = self.param(key) else {
+            return Ok(None);
+        };
+        value.parse().map(Some).map_err(|e: T::Err| ParamError {
+            name: key.to_string(),
+            value: value.clone(),
+            reason: e.to_string(),
+        })
+    }
+}
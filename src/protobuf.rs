@@ -0,0 +1,80 @@
+// Protocol Buffers bodies, behind the `protobuf` feature
+
+use crate::error::error_response;
+use crate::mime::Mime;
+use crate::request::Request;
+use crate::response::Response;
+
+use std::fmt;
+
+// Content-Type of protobuf responses
+const PROTOBUF_TYPE: &str = "application/x-protobuf";
+
+#[derive(Debug)]
+pub enum ProtobufError {
+    // Content-Type was set to something other than protobuf
+    UnsupportedMediaType(String),
+    // The body doesn't decode as the target message
+    Invalid(prost::DecodeError),
+}
+
+impl ProtobufError {
+    // 415 when the body isn't declared as protobuf, 400 when the bytes
+    // don't decode as the wanted message
+    pub fn status(&self) -> u16 {
+        match self {
+            ProtobufError::UnsupportedMediaType(_) => 415,
+            ProtobufError::Invalid(_) => 400,
+        }
+    }
+
+}
+
+error_response!(ProtobufError);
+
+impl fmt::Display for ProtobufError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtobufError::UnsupportedMediaType(content_type) => {
+                write!(f, "expected a protobuf body, got Content-Type {}", content_type)
+            }
+            ProtobufError::Invalid(e) => write!(f, "invalid protobuf body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProtobufError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProtobufError::UnsupportedMediaType(_) => None,
+            ProtobufError::Invalid(e) => Some(e),
+        }
+    }
+}
+
+impl Request {
+    // Decode the body, e.g. let order: Order = req.protobuf()?. A missing
+    // Content-Type is accepted; any other than application/x-protobuf,
+    // application/protobuf or application/vnd.google.protobuf is not.
+    pub fn protobuf<T: prost::Message + Default>(&self) -> Result<T, ProtobufError> {
+        if let Some(content_type) = self.header("content-type") {
+            let is_protobuf = Mime::parse(content_type).is_some_and(|mime| {
+                matches!(
+                    mime.essence(),
+                    "application/x-protobuf" | "application/protobuf" | "application/vnd.google.protobuf"
+                )
+            });
+            if !is_protobuf {
+                return Err(ProtobufError::UnsupportedMediaType(content_type.clone()));
+            }
+        }
+        T::decode(self.body.as_slice()).map_err(ProtobufError::Invalid)
+    }
+}
+
+impl Response {
+    // 200 with `message` encoded as application/x-protobuf
+    pub fn protobuf<T: prost::Message>(message: &T) -> Response {
+        Response::new(200, message.encode_to_vec(), PROTOBUF_TYPE)
+    }
+}
@@ -0,0 +1,479 @@
+use crate::request::Request;
+use crate::response::Response;
+use crate::writable::write_atomic;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Headers that only make sense for a single hop and must not be forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// Largest upstream response read unless set with max_response_size
+const DEFAULT_MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct Proxy {
+    upstream: String,
+    cache_dir: Option<PathBuf>,
+    timeout: Duration,
+    max_response_size: usize,
+}
+
+// A response as received from the upstream, before it is turned into a Response
+struct UpstreamResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+// What we know about a stored response
+struct CacheEntry {
+    stored_at: u64,
+    max_age: Option<u64>,
+    must_revalidate: bool,
+    response: UpstreamResponse,
+}
+
+impl Proxy {
+    pub fn new(upstream: &str) -> Self {
+        let upstream = upstream.trim_start_matches("http://").trim_end_matches('/');
+        Self {
+            upstream: upstream.to_string(),
+            cache_dir: None,
+            timeout: Duration::from_secs(30),
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+        }
+    }
+
+    // Larger upstream responses (64 MiB by default) fail with 502
+    pub fn max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = bytes;
+        self
+    }
+
+    pub fn cache_dir(mut self, dir: &str) -> Self {
+        self.cache_dir = Some(PathBuf::from(dir));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Forward a request upstream, answering from the on-disk cache when allowed
+    pub fn forward(&self, req: &Request) -> Result<Response, String> {
+        let target = req.target();
+        let cacheable = req.method == "GET" && self.cache_dir.is_some();
+        let req_cc = cache_control(req.header("cache-control").map(|s| s.as_str()));
+
+        if !cacheable || req_cc.contains_key("no-store") {
+            return self.fetch(req, &target, None).map(into_response);
+        }
+
+        // The cache is shared by every client, so an entry is per host and
+        // per value of each header the upstream said it Varies on
+        let host = req.header("host").map_or("", |h| h.as_str());
+        let primary = cache_key(&[&req.method, host, &target]);
+        let key = variant_key(&primary, &self.load_vary(&primary), req);
+        let cached = self.load(&key);
+
+        if let Some(entry) = &cached
+            && !req_cc.contains_key("no-cache")
+            && entry.is_fresh()
+        {
+            return Ok(into_response(entry.response.clone_with_age(entry.age(), "HIT")));
+        }
+
+        let etag = cached.as_ref().and_then(|e| e.response.header("etag"));
+        let upstream = self.fetch(req, &target, etag.as_deref())?;
+
+        if upstream.status == 304
+            && let Some(mut entry) = cached
+        {
+            // Revalidated: keep the stored body, refresh its freshness data
+            let cc = cache_control(upstream.header("cache-control").as_deref());
+            entry.stored_at = now_secs();
+            entry.max_age = max_age(&cc).or(entry.max_age);
+            self.store(&key, &entry);
+            return Ok(into_response(entry.response.clone_with_age(0, "REVALIDATED")));
+        }
+
+        let cc = cache_control(upstream.header("cache-control").as_deref());
+        if upstream.status == 200 && is_storable(req, &upstream, &cc) {
+            let vary = vary_names(&upstream);
+            self.store_vary(&primary, &vary);
+            let key = variant_key(&primary, &vary, req);
+            let entry = CacheEntry {
+                stored_at: now_secs(),
+                max_age: max_age(&cc),
+                must_revalidate: cc.contains_key("no-cache"),
+                response: upstream,
+            };
+            self.store(&key, &entry);
+            return Ok(into_response(entry.response.clone_with_age(0, "MISS")));
+        }
+
+        Ok(into_response(upstream))
+    }
+
+    fn fetch(&self, req: &Request, target: &str, etag: Option<&str>) -> Result<UpstreamResponse, String> {
+        let mut stream = TcpStream::connect(&self.upstream)
+            .map_err(|e| format!("upstream {} unreachable: {}", self.upstream, e))?;
+        let _ = stream.set_read_timeout(Some(self.timeout));
+        let _ = stream.set_write_timeout(Some(self.timeout));
+
+        let mut head = format!("{} {} HTTP/1.1\r\n", req.method, target);
+        for (k, v) in &req.headers {
+            if HOP_BY_HOP.contains(&k.as_str()) || k == "content-length" {
+                continue;
+            }
+            if etag.is_some() && k == "if-none-match" {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", k, v));
+        }
+        if !req.headers.contains_key("host") {
+            head.push_str(&format!("host: {}\r\n", self.upstream));
+        }
+        if let Some(etag) = etag {
+            head.push_str(&format!("if-none-match: {}\r\n", etag));
+        }
+        if !req.body.is_empty() {
+            head.push_str(&format!("content-length: {}\r\n", req.body.len()));
+        }
+        head.push_str("connection: close\r\n\r\n");
+
+        let mut out = head.into_bytes();
+        out.extend_from_slice(&req.body);
+        stream.write_all(&out).map_err(|e| e.to_string())?;
+
+        let mut raw = Vec::new();
+        // The head may take a little more than the body limit
+        let limit = self.max_response_size as u64 + 64 * 1024;
+        (&mut stream).take(limit + 1).read_to_end(&mut raw).map_err(|e| e.to_string())?;
+        if raw.len() as u64 > limit {
+            return Err(format!("upstream response larger than {} bytes", self.max_response_size));
+        }
+        let response = parse_upstream_response(&raw).ok_or_else(|| "malformed upstream response".to_string())?;
+        if response.body.len() > self.max_response_size {
+            return Err(format!("upstream response larger than {} bytes", self.max_response_size));
+        }
+        Ok(response)
+    }
+
+    // Names of the request headers the stored variants of `primary` differ
+    // by, as the upstream's last Vary said
+    fn load_vary(&self, primary: &str) -> Vec<String> {
+        let Some(path) = self.cache_path(&format!("{}.vary", primary)) else {
+            return Vec::new();
+        };
+        fs::read_to_string(path)
+            .map(|names| names.lines().map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    fn store_vary(&self, primary: &str, names: &[String]) {
+        if let Some(path) = self.cache_path(&format!("{}.vary", primary)) {
+            let _ = write_atomic(&path, names.join("\n").as_bytes());
+        }
+    }
+
+    fn cache_path(&self, key: &str) -> Option<PathBuf> {
+        self.cache_dir.as_ref().map(|dir| dir.join(format!("{}.cache", key)))
+    }
+
+    fn load(&self, key: &str) -> Option<CacheEntry> {
+        let data = fs::read(self.cache_path(key)?).ok()?;
+        CacheEntry::decode(&data)
+    }
+
+    fn store(&self, key: &str, entry: &CacheEntry) {
+        // Another request may be reading this entry right now
+        if let Some(path) = self.cache_path(key)
+            && let Err(e) = write_atomic(&path, &entry.encode())
+        {
+            eprintln!("Proxy cache write failed for {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl UpstreamResponse {
+    fn header(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.clone())
+    }
+
+    fn clone_with_age(&self, age: u64, status: &str) -> UpstreamResponse {
+        let mut headers: Vec<_> = self
+            .headers
+            .iter()
+            .filter(|(k, _)| !k.eq_ignore_ascii_case("age"))
+            .cloned()
+            .collect();
+        headers.push(("Age".to_string(), age.to_string()));
+        headers.push(("X-Cache".to_string(), status.to_string()));
+        UpstreamResponse {
+            status: self.status,
+            headers,
+            body: self.body.clone(),
+        }
+    }
+}
+
+impl CacheEntry {
+    fn age(&self) -> u64 {
+        now_secs().saturating_sub(self.stored_at)
+    }
+
+    fn is_fresh(&self) -> bool {
+        if self.must_revalidate {
+            return false;
+        }
+        matches!(self.max_age, Some(max) if self.age() < max)
+    }
+
+    // Layout: metadata lines, response headers, blank line, raw body. The
+    // body length is among the metadata so a cut-short file isn't served.
+    fn encode(&self) -> Vec<u8> {
+        let mut head = format!(
+            "{}\n{}\n{}\n{}\n{}\n",
+            self.response.status,
+            self.stored_at,
+            self.max_age.map(|m| m.to_string()).unwrap_or_default(),
+            self.must_revalidate,
+            self.response.body.len()
+        );
+        for (k, v) in &self.response.headers {
+            head.push_str(&format!("{}: {}\n", k, v));
+        }
+        head.push('\n');
+        let mut out = head.into_bytes();
+        out.extend_from_slice(&self.response.body);
+        out
+    }
+
+    fn decode(data: &[u8]) -> Option<CacheEntry> {
+        let split = data.windows(2).position(|w| w == b"\n\n")?;
+        let head = std::str::from_utf8(&data[..split]).ok()?;
+        let mut lines = head.lines();
+        let status = lines.next()?.parse().ok()?;
+        let stored_at = lines.next()?.parse().ok()?;
+        let max_age = lines.next()?.parse().ok();
+        let must_revalidate = lines.next()? == "true";
+        let body_len: usize = lines.next()?.parse().ok()?;
+        let body = &data[split + 2..];
+        if body.len() != body_len {
+            return None;
+        }
+        let headers = lines
+            .filter_map(|l| l.split_once(": "))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Some(CacheEntry {
+            stored_at,
+            max_age,
+            must_revalidate,
+            response: UpstreamResponse {
+                status,
+                headers,
+                body: body.to_vec(),
+            },
+        })
+    }
+}
+
+fn into_response(upstream: UpstreamResponse) -> Response {
+    let content_type = upstream
+        .header("content-type")
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let mut response = Response::new(upstream.status, upstream.body, &content_type);
+    for (k, v) in upstream.headers {
+        let lower = k.to_ascii_lowercase();
+        if HOP_BY_HOP.contains(&lower.as_str()) || lower == "content-length" || lower == "content-type" {
+            continue;
+        }
+        response.headers.append(k, v);
+    }
+    response
+}
+
+fn parse_upstream_response(raw: &[u8]) -> Option<UpstreamResponse> {
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&raw[..split]);
+    let mut lines = head.lines();
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let mut body = raw[split + 4..].to_vec();
+    let chunked = headers.iter().any(|(k, v)| {
+        k.eq_ignore_ascii_case("transfer-encoding") && v.to_ascii_lowercase().contains("chunked")
+    });
+    if chunked {
+        body = decode_chunked(&body)?;
+    } else if let Some(len) = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+    {
+        body.truncate(len);
+    }
+
+    Some(UpstreamResponse { status, headers, body })
+}
+
+fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|w| w == b"\r\n")?;
+        let size_line = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size_hex = size_line.split(';').next()?.trim();
+        let size = usize::from_str_radix(size_hex, 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+fn cache_control(value: Option<&str>) -> HashMap<String, String> {
+    value
+        .unwrap_or("")
+        .split(',')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| match d.split_once('=') {
+            Some((k, v)) => (k.trim().to_ascii_lowercase(), v.trim().trim_matches('"').to_string()),
+            None => (d.to_ascii_lowercase(), String::new()),
+        })
+        .collect()
+}
+
+// s-maxage wins over max-age for a shared cache like this one
+fn max_age(cc: &HashMap<String, String>) -> Option<u64> {
+    cc.get("s-maxage")
+        .or_else(|| cc.get("max-age"))
+        .and_then(|v| v.parse().ok())
+}
+
+// Whether a shared cache may keep this response (RFC 9111 section 3).
+// Answers to requests with credentials are only kept when the upstream
+// marks them as shareable, and Vary: * can never be matched.
+fn is_storable(req: &Request, upstream: &UpstreamResponse, cc: &HashMap<String, String>) -> bool {
+    if cc.contains_key("no-store") || cc.contains_key("private") {
+        return false;
+    }
+    if vary_names(upstream).iter().any(|name| name == "*") || upstream.header("set-cookie").is_some() {
+        return false;
+    }
+    let credentials = req.header("authorization").is_some() || req.header("cookie").is_some();
+    !credentials || ["public", "s-maxage", "must-revalidate"].iter().any(|d| cc.contains_key(*d))
+}
+
+// The upstream's Vary names, lowercased
+fn vary_names(upstream: &UpstreamResponse) -> Vec<String> {
+    let mut names: Vec<String> = upstream
+        .headers
+        .iter()
+        .filter(|(k, _)| k.eq_ignore_ascii_case("vary"))
+        .flat_map(|(_, v)| v.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// The key of the variant of `primary` matching the request's values of
+// the `vary` headers
+fn variant_key(primary: &str, vary: &[String], req: &Request) -> String {
+    if vary.is_empty() {
+        return primary.to_string();
+    }
+    let mut parts = vec![primary.to_string()];
+    for name in vary {
+        parts.push(format!("{}: {}", name, req.header(name).map_or("", |v| v.as_str())));
+    }
+    let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+    cache_key(&parts)
+}
+
+// FNV-1a over the parts; stable across runs so the cache survives restarts
+fn cache_key(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for (i, part) in parts.iter().enumerate() {
+        // A separator no header or target contains, so parts can't run together
+        let separator: &[u8] = if i == 0 { b"" } else { b"\n" };
+        for b in separator.iter().chain(part.as_bytes()) {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(body: &[u8]) -> CacheEntry {
+        CacheEntry {
+            stored_at: 1_700_000_000,
+            max_age: Some(60),
+            must_revalidate: false,
+            response: UpstreamResponse {
+                status: 200,
+                headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+                body: body.to_vec(),
+            },
+        }
+    }
+
+    #[test]
+    fn cache_entries_roundtrip() {
+        let decoded = CacheEntry::decode(&entry(b"hello\n\nworld").encode()).unwrap();
+        assert_eq!(decoded.stored_at, 1_700_000_000);
+        assert_eq!(decoded.max_age, Some(60));
+        assert_eq!(decoded.response.status, 200);
+        assert_eq!(decoded.response.header("content-type").as_deref(), Some("text/plain"));
+        assert_eq!(decoded.response.body, b"hello\n\nworld");
+    }
+
+    #[test]
+    fn truncated_entries_are_rejected() {
+        let mut data = entry(b"hello world").encode();
+        data.truncate(data.len() - 3);
+        assert!(CacheEntry::decode(&data).is_none());
+        let mut data = entry(b"hello world").encode();
+        data.push(b'!');
+        assert!(CacheEntry::decode(&data).is_none());
+    }
+}
@@ -0,0 +1,527 @@
+// A small regular expression engine for route constraints and validation.
+// Patterns compile to an NFA that is run breadth-first (Pike VM), so matching
+// is linear in the input: request paths are attacker-controlled and a
+// backtracking engine could be made to spin on them.
+//
+// Supported: literals, `.`, `[...]` classes with ranges and negation, `\d \w
+// \s \D \W \S`, escaped metacharacters, `^ $`, groups `(...)` and `(?:...)`,
+// `|`, and the quantifiers `* + ? {n} {n,} {n,m}` (a trailing `?` for lazy
+// matching is accepted; it makes no difference without captures).
+
+use std::fmt;
+
+// Counted repetition is expanded, so cap the program size
+const MAX_PROGRAM: usize = 10_000;
+
+#[derive(Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+    classes: Vec<Class>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RegexError {
+    pattern: String,
+    message: String,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid regex `{}`: {}", self.pattern, self.message)
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Regex({:?})", self.pattern)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let hit = self.items.iter().any(|item| match *item {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Digit(neg) => c.is_ascii_digit() != neg,
+            ClassItem::Word(neg) => (c.is_alphanumeric() || c == '_') != neg,
+            ClassItem::Space(neg) => c.is_whitespace() != neg,
+        });
+        hit != self.negated
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(usize),
+    Start,
+    End,
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        let error = |message: String| RegexError {
+            pattern: pattern.to_string(),
+            message,
+        };
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let node = parser.alternation().map_err(error)?;
+        if parser.pos < parser.chars.len() {
+            return Err(error(format!("unmatched `)` at {}", parser.pos)));
+        }
+        let mut compiler = Compiler {
+            program: Vec::new(),
+            classes: Vec::new(),
+        };
+        compiler.emit_node(&node).map_err(error)?;
+        compiler.program.push(Inst::Match);
+        Ok(Self {
+            pattern: pattern.to_string(),
+            program: compiler.program,
+            classes: compiler.classes,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // True if the pattern matches anywhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        self.run(text, false)
+    }
+
+    // True if the pattern matches all of `text`
+    pub fn is_full_match(&self, text: &str) -> bool {
+        self.run(text, true)
+    }
+
+    fn run(&self, text: &str, full: bool) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        for pos in 0..=chars.len() {
+            // A search may start anywhere, a full match only at the beginning
+            if pos == 0 || !full {
+                let matched = self.add(&mut current, 0, pos, chars.len());
+                if matched && !full {
+                    return true;
+                }
+            }
+            let Some(&c) = chars.get(pos) else {
+                break;
+            };
+            if full && current.list.is_empty() {
+                return false;
+            }
+            next.clear();
+            for i in 0..current.list.len() {
+                let pc = current.list[i];
+                let step = match self.program[pc] {
+                    Inst::Char(expected) => c == expected,
+                    Inst::Any => c != '\n',
+                    Inst::Class(idx) => self.classes[idx].matches(c),
+                    _ => false,
+                };
+                if step && self.add(&mut next, pc + 1, pos + 1, chars.len()) && !full {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        // Threads left at the end that reached Match were recorded by `add`
+        current.matched_at_end
+    }
+
+    // Follow jumps and assertions from `pc`, queueing the instructions that
+    // consume input. Returns true if Match is reachable.
+    fn add(&self, threads: &mut Threads, pc: usize, pos: usize, len: usize) -> bool {
+        if threads.seen[pc] == threads.generation {
+            return false;
+        }
+        threads.seen[pc] = threads.generation;
+        match self.program[pc] {
+            Inst::Jmp(target) => self.add(threads, target, pos, len),
+            Inst::Split(a, b) => {
+                let first = self.add(threads, a, pos, len);
+                let second = self.add(threads, b, pos, len);
+                first || second
+            }
+            Inst::Start => pos == 0 && self.add(threads, pc + 1, pos, len),
+            Inst::End => pos == len && self.add(threads, pc + 1, pos, len),
+            Inst::Match => {
+                if pos == len {
+                    threads.matched_at_end = true;
+                }
+                true
+            }
+            _ => {
+                threads.list.push(pc);
+                false
+            }
+        }
+    }
+}
+
+// The set of live NFA states at one input position
+struct Threads {
+    list: Vec<usize>,
+    seen: Vec<u32>,
+    generation: u32,
+    matched_at_end: bool,
+}
+
+impl Threads {
+    fn new(size: usize) -> Self {
+        Self {
+            list: Vec::new(),
+            seen: vec![0; size],
+            generation: 1,
+            matched_at_end: false,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.generation += 1;
+        self.matched_at_end = false;
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concat()?];
+        while self.eat('|') {
+            branches.push(self.concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alt(branches)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            items.push(self.quantifier(atom)?);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("unexpected end of pattern")?;
+        self.pos += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                if self.eat('?') && !self.eat(':') {
+                    return Err(format!("unsupported group syntax at {}", self.pos));
+                }
+                let inner = self.alternation()?;
+                if !self.eat(')') {
+                    return Err("unclosed `(`".to_string());
+                }
+                inner
+            }
+            '[' => Node::Class(self.class()?),
+            '\\' => self.escape()?,
+            '*' | '+' | '?' => return Err(format!("nothing to repeat at {}", self.pos - 1)),
+            '{' if self.counted_at(self.pos) => {
+                return Err(format!("nothing to repeat at {}", self.pos - 1));
+            }
+            c => Node::Char(c),
+        })
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("trailing `\\`")?;
+        self.pos += 1;
+        let class = |item| {
+            Node::Class(Class {
+                items: vec![item],
+                negated: false,
+            })
+        };
+        Ok(match c {
+            'd' => class(ClassItem::Digit(false)),
+            'D' => class(ClassItem::Digit(true)),
+            'w' => class(ClassItem::Word(false)),
+            'W' => class(ClassItem::Word(true)),
+            's' => class(ClassItem::Space(false)),
+            'S' => class(ClassItem::Space(true)),
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            'r' => Node::Char('\r'),
+            c if c.is_ascii_alphanumeric() => return Err(format!("unknown escape `\\{}`", c)),
+            c => Node::Char(c),
+        })
+    }
+
+    fn class(&mut self) -> Result<Class, String> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or("unclosed `[`")?;
+            self.pos += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = if c == '\\' {
+                let e = self.peek().ok_or("unclosed `[`")?;
+                self.pos += 1;
+                match e {
+                    'd' | 'D' => {
+                        items.push(ClassItem::Digit(e == 'D'));
+                        continue;
+                    }
+                    'w' | 'W' => {
+                        items.push(ClassItem::Word(e == 'W'));
+                        continue;
+                    }
+                    's' | 'S' => {
+                        items.push(ClassItem::Space(e == 'S'));
+                        continue;
+                    }
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    e => e,
+                }
+            } else {
+                c
+            };
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                self.pos += 1;
+                let mut hi = self.peek().ok_or("unclosed `[`")?;
+                self.pos += 1;
+                if hi == '\\' {
+                    hi = self.peek().ok_or("unclosed `[`")?;
+                    self.pos += 1;
+                }
+                if hi < lo {
+                    return Err(format!("invalid class range {}-{}", lo, hi));
+                }
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Class { items, negated })
+    }
+
+    // `{` starts a counted repetition only when it looks like one; `start`
+    // is just past the brace
+    fn counted_at(&self, start: usize) -> bool {
+        let rest: String = self.chars[start..].iter().take_while(|&&c| c != '}').collect();
+        self.chars.get(start + rest.len()) == Some(&'}')
+            && !rest.is_empty()
+            && rest.chars().all(|c| c.is_ascii_digit() || c == ',')
+            && rest.chars().next().is_some_and(|c| c.is_ascii_digit())
+    }
+
+    fn quantifier(&mut self, node: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') if self.counted_at(self.pos + 1) => {
+                self.pos += 1;
+                let mut spec = String::new();
+                while let Some(c) = self.peek() {
+                    self.pos += 1;
+                    if c == '}' {
+                        break;
+                    }
+                    spec.push(c);
+                }
+                let parse = |s: &str| s.parse::<u32>().map_err(|_| format!("bad repetition `{{{}}}`", spec));
+                let (min, max) = match spec.split_once(',') {
+                    None => (parse(&spec)?, Some(parse(&spec)?)),
+                    Some((min, "")) => (parse(min)?, None),
+                    Some((min, max)) => (parse(min)?, Some(parse(max)?)),
+                };
+                if max.is_some_and(|max| max < min) {
+                    return Err(format!("bad repetition `{{{}}}`", spec));
+                }
+                // Already past the quantifier
+                self.eat('?');
+                return self.quantifier(Node::Repeat {
+                    node: Box::new(node),
+                    min,
+                    max,
+                });
+            }
+            _ => return Ok(node),
+        };
+        self.pos += 1;
+        self.eat('?');
+        if matches!(node, Node::Start | Node::End) {
+            return Err("cannot repeat an anchor".to_string());
+        }
+        self.quantifier(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+        })
+    }
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+    classes: Vec<Class>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.program.len() >= MAX_PROGRAM {
+            return Err("pattern too large".to_string());
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    fn emit_node(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => {
+                self.push(Inst::Char(*c))?;
+            }
+            Node::Any => {
+                self.push(Inst::Any)?;
+            }
+            Node::Class(class) => {
+                self.classes.push(class.clone());
+                self.push(Inst::Class(self.classes.len() - 1))?;
+            }
+            Node::Start => {
+                self.push(Inst::Start)?;
+            }
+            Node::End => {
+                self.push(Inst::End)?;
+            }
+            Node::Concat(items) => {
+                for item in items {
+                    self.emit_node(item)?;
+                }
+            }
+            Node::Alt(branches) => {
+                // split L1, next; L1: a; jmp end; next: split L2, ...
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.push(Inst::Split(0, 0))?;
+                        self.emit_node(branch)?;
+                        jumps.push(self.push(Inst::Jmp(0))?);
+                        let next = self.program.len();
+                        self.program[split] = Inst::Split(split + 1, next);
+                    } else {
+                        self.emit_node(branch)?;
+                    }
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    self.emit_node(node)?;
+                }
+                match max {
+                    None => {
+                        // loop: split body, end; body; jmp loop
+                        let split = self.push(Inst::Split(0, 0))?;
+                        self.emit_node(node)?;
+                        self.push(Inst::Jmp(split))?;
+                        let end = self.program.len();
+                        self.program[split] = Inst::Split(split + 1, end);
+                    }
+                    Some(max) => {
+                        // Each optional copy may be skipped to the very end
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0))?);
+                            self.emit_node(node)?;
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.program[split] = Inst::Split(split + 1, end);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
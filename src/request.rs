@@ -1,42 +1,523 @@
+use crate::assets::Assets;
+use crate::base64;
+use crate::body::BodyReader;
+use crate::extensions::Extensions;
+use crate::language;
+use crate::mime::{self, Mime};
+use crate::router::Params;
+use crate::static_files::parse_ranges;
+use crate::urls::{form_decode, NamedRoutes};
+
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 pub struct Request {
     pub method: String,
     pub path: String,
+    // From the request line, e.g. "HTTP/1.1"; "HTTP/1.1" if it was missing
+    pub version: String,
     pub raw: String,
+    // Keyed by lowercased name; a header sent more than once has its values
+    // joined with ", " ("; " for Cookie). See headers_all for each one.
     pub headers: HashMap<String, String>,
+    // Every header line in arrival order, name lowercased
+    pub(crate) header_lines: Vec<(String, String)>,
     pub query: HashMap<String, String>,
     pub body: Vec<u8>,
+    // Fields sent after a chunked body, e.g. checksums computed while
+    // streaming. Kept apart from `headers` since they arrive after the
+    // handler-relevant metadata and are not covered by the same checks.
+    pub trailers: HashMap<String, String>,
+    // Set instead of `body` for routes registered with .stream_body()
+    pub(crate) body_reader: Mutex<Option<BodyReader>>,
+    // Parameters of the matched route; empty until routing, so global
+    // before middleware never sees them
+    pub path_params: Params,
+    pub(crate) route_meta: Extensions,
+    // Named routes of the router serving the request, for url_for
+    pub(crate) named_routes: NamedRoutes,
+    // Fingerprinted static names of the server, for asset
+    pub(crate) assets: Assets,
+    // Typed values attached by middleware for handlers, e.g. the user an
+    // auth middleware decoded: req.extensions.insert(CurrentUser { .. })
+    // then req.extensions.get::<CurrentUser>()
+    pub extensions: Extensions,
+    // Server-side address the connection arrived on
+    pub local_addr: Option<SocketAddr>,
+    // The client's address as seen by this server; behind a proxy that is
+    // the proxy's address
+    pub remote_addr: Option<SocketAddr>,
+    pub(crate) client_ip: Option<IpAddr>,
+    // Only kept when the message isn't valid UTF-8; otherwise `raw` is the
+    // exact message and the bytes aren't stored twice. See raw_parts.
+    pub(crate) raw_bytes: Vec<u8>,
+    // The client's X-Request-Id if it sent a usable one, otherwise one
+    // generated for this request. Also set as the X-Request-Id request
+    // header (so proxied requests carry it) and response header.
+    pub request_id: String,
+    pub(crate) deadline: Option<Instant>,
 }
 
 impl Request {
+    pub fn builder() -> RequestBuilder {
+        RequestBuilder::default()
+    }
+
     pub fn header(&self, key: &str) -> Option<&String> {
         self.headers.get(&key.to_ascii_lowercase())
     }
 
+    // The value of each line carrying this header, in the order sent; empty
+    // if absent
+    pub fn headers_all(&self, key: &str) -> Vec<&str> {
+        self.header_lines
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    // None if absent or not a plain number
+    pub fn content_length(&self) -> Option<usize> {
+        let value = self.header("content-length")?.trim();
+        value.bytes().all(|b| b.is_ascii_digit()).then(|| value.parse().ok())?
+    }
+
+    pub fn content_type(&self) -> Option<Mime> {
+        Mime::parse(self.header("content-type")?)
+    }
+
+    // Whether the Accept header allows `media_type` (with q > 0). A request
+    // without Accept accepts anything.
+    pub fn accepts(&self, media_type: &str) -> bool {
+        let (Some(header), Some(media_type)) = (self.header("accept"), Mime::parse(media_type)) else {
+            return self.header("accept").is_none();
+        };
+        mime::quality(&mime::parse_accept(header), &media_type) > 0.0
+    }
+
+    // The entry of `offered` the Accept header rates highest, ties going to
+    // the earlier entry, e.g. preferred_type(&["application/json",
+    // "text/html"]) to serve a page and an API from one route. The first
+    // offer if there is no Accept header; None if nothing offered is
+    // acceptable.
+    pub fn preferred_type<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let Some(header) = self.header("accept") else {
+            return offered.first().copied();
+        };
+        let ranges = mime::parse_accept(header);
+        let mut best: Option<(&str, f32)> = None;
+        for &media_type in offered {
+            let q = Mime::parse(media_type).map_or(0.0, |m| mime::quality(&ranges, &m));
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((media_type, q));
+            }
+        }
+        best.map(|(media_type, _)| media_type)
+    }
+
+    // The entry of `offered` the Accept-Language header rates highest, ties
+    // going to the earlier entry, e.g. preferred_language(&["en", "de",
+    // "fr"]). The first offer if there is no Accept-Language header; None if
+    // nothing offered is acceptable.
+    pub fn preferred_language<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let Some(header) = self.header("accept-language") else {
+            return offered.first().copied();
+        };
+        let ranges = language::parse_accept_language(header);
+        let mut best: Option<(&str, f32)> = None;
+        for &tag in offered {
+            let q = language::quality(&ranges, tag);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((tag, q));
+            }
+        }
+        best.map(|(tag, _)| tag)
+    }
+
+    // User name and password from `Authorization: Basic ...`. None if the
+    // header is missing, uses another scheme or isn't valid base64 of
+    // `user:password`.
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let (scheme, credentials) = self.header("authorization")?.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(base64::decode(credentials.trim())?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+
+    // Byte ranges asked for with `Range: bytes=...`, resolved against a body
+    // of `total` bytes: inclusive (start, end) pairs, sorted with overlaps
+    // merged. None if there is no usable Range header (serve the whole
+    // body); an empty list if no range is satisfiable (416).
+    pub fn range(&self, total: u64) -> Option<Vec<(u64, u64)>> {
+        parse_ranges(self.header("range")?, total)
+    }
+
+    // Sent by XMLHttpRequest wrappers such as jQuery's as X-Requested-With
+    pub fn is_ajax(&self) -> bool {
+        self.header("x-requested-with")
+            .is_some_and(|v| v.eq_ignore_ascii_case("xmlhttprequest"))
+    }
+
+    pub fn trailer(&self, key: &str) -> Option<&String> {
+        self.trailers.get(&key.to_ascii_lowercase())
+    }
+
     pub fn param(&self, key: &str) -> Option<&String> {
         self.query.get(key)
     }
 
+    pub fn path_param(&self, key: &str) -> Option<&String> {
+        self.path_params.get(key)
+    }
+
+    // Metadata the matched route was given with RouteHandle::meta
+    pub fn route_meta<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.route_meta.get()
+    }
+
+    // URL of a route named with route_named on the router serving this
+    // request, e.g. req.url_for("user_detail", &[("id", "42")]) ->
+    // Some("/users/42"). See Router::url_for.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        self.named_routes.url_for(name, params)
+    }
+
+    // Replace `{{ url_for("user_detail", id=42) }}` placeholders in a
+    // template with URLs of the router serving this request
+    pub fn expand_urls(&self, template: &str) -> String {
+        self.named_routes.expand_urls(template)
+    }
+
+    // URL for a static file, fingerprinted when the server fingerprints
+    // assets, e.g. req.asset("app.css") -> "/app.1a2b3c4d.css"
+    pub fn asset(&self, path: &str) -> String {
+        self.assets.asset(path)
+    }
+
+    // Replace `{{ asset("app.css") }}` placeholders in a template with
+    // asset(), e.g. on a template engine's output
+    pub fn expand_assets(&self, template: &str) -> String {
+        self.assets.expand(template)
+    }
+
+    // The body as a stream, for routes registered with .stream_body(); their
+    // `body` stays empty. None for other routes or once taken.
+    pub fn body_reader(&self) -> Option<BodyReader> {
+        self.body_reader.lock().unwrap().take()
+    }
+
+    // API version asked for with `X-API-Version: 2` (or "v2"), or in Accept
+    // as `application/vnd.example.v2+json` or `application/json; version=2`
+    pub fn api_version(&self) -> Option<u32> {
+        if let Some(version) = self.header("x-api-version") {
+            let version = version.trim();
+            return version.strip_prefix(['v', 'V']).unwrap_or(version).parse().ok();
+        }
+        let accept = self.header("accept")?;
+        accept.split(',').find_map(|media_range| {
+            let mut parts = media_range.split(';').map(str::trim);
+            let media_type = parts.next()?;
+            let from_param = parts.find_map(|param| param.strip_prefix("version=")?.parse().ok());
+            from_param.or_else(|| {
+                let subtype = media_type.split_once('/')?.1;
+                let subtype = subtype.split('+').next()?;
+                subtype.rsplit('.').next()?.strip_prefix('v')?.parse().ok()
+            })
+        })
+    }
+
+    // The request exactly as received (head and body), for signature checks
+    // such as webhook HMACs; `raw` is a lossy UTF-8 copy
+    pub fn raw_bytes(&self) -> &[u8] {
+        if self.raw_bytes.is_empty() { self.raw.as_bytes() } else { &self.raw_bytes }
+    }
+
+    // The originating client's address. Same as remote_addr's IP unless the
+    // server was given trusted_proxies and the request came through them, in
+    // which case it is taken from Forwarded or X-Forwarded-For.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    // When the server's request_deadline for this request runs out; None if
+    // no deadline is configured
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // What is left of the deadline, zero once it has passed
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    // True if the client connected over IPv6. IPv4 clients of a dual-stack
+    // listener show up as IPv4-mapped addresses and count as IPv4.
+    pub fn is_ipv6(&self) -> bool {
+        self.local_addr
+            .is_some_and(|addr| addr.ip().to_canonical().is_ipv6())
+    }
+
     // Parse URL query parameters
     pub fn query_params(&self) -> HashMap<String, String> {
         self.query.clone()
     }
 
+    // Every value of a repeated query parameter, e.g. ["1", "2"] for
+    // ?id=1&id=2; `query` only keeps the last one
+    pub fn query_all(&self, key: &str) -> Vec<String> {
+        match self.target().split_once('?') {
+            Some((_, query)) => values_of(query.as_bytes(), key),
+            None => Vec::new(),
+        }
+    }
+
+    // Cookies from the Cookie header(s), by name. Double quotes around a
+    // value are removed; a name sent twice keeps its last value.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        let Some(header) = self.header("cookie") else {
+            return HashMap::new();
+        };
+        header
+            .split(';')
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .map(|(name, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.trim().to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().remove(name)
+    }
+
     // Parse URL-encoded form data from body
     pub fn form_data(&self) -> HashMap<String, String> {
         parse_urlencoded(&self.body)
     }
+
+    // Every value of a repeated form field, e.g. checkboxes sharing a name
+    pub fn form_all(&self, key: &str) -> Vec<String> {
+        values_of(&self.body, key)
+    }
+
+    // The request line target in origin-form, query string included
+    pub(crate) fn target(&self) -> String {
+        let target = self
+            .raw
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap_or(&self.path);
+        split_request_target(target).1
+    }
+}
+
+// Builds a Request without a connection, for unit-testing handlers:
+//
+//     let req = Request::builder()
+//         .method("POST")
+//         .path("/echo?lang=en")
+//         .header("Content-Type", "text/plain")
+//         .body("hi")
+//         .build();
+//     let response = echo(&req, &Params::default());
+#[derive(Default)]
+pub struct RequestBuilder {
+    method: Option<String>,
+    target: Option<String>,
+    version: Option<String>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    remote_addr: Option<SocketAddr>,
+    deadline: Option<Instant>,
 }
 
-// Helper to parse URL-encoded data
-fn parse_urlencoded(data: &[u8]) -> HashMap<String, String> {
+impl RequestBuilder {
+    // GET if not set
+    pub fn method(mut self, method: &str) -> Self {
+        self.method = Some(method.to_ascii_uppercase());
+        self
+    }
+
+    // Path with an optional query string; "/" if not set
+    pub fn path(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    // HTTP/1.1 if not set
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_string());
+        self
+    }
+
+    // Adds a header line; call again with the same name to repeat it
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    // Also sets Content-Length unless a header already frames the body
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn remote_addr(mut self, addr: SocketAddr) -> Self {
+        self.remote_addr = Some(addr);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn build(self) -> Request {
+        let method = self.method.unwrap_or_else(|| "GET".to_string());
+        let target = self.target.unwrap_or_else(|| "/".to_string());
+        let version = self.version.unwrap_or_else(|| "HTTP/1.1".to_string());
+        let mut header_lines = self.headers;
+        let framed = header_lines
+            .iter()
+            .any(|(name, _)| name == "content-length" || name == "transfer-encoding");
+        if !self.body.is_empty() && !framed {
+            header_lines.push(("content-length".to_string(), self.body.len().to_string()));
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), parse_urlencoded(query.as_bytes())),
+            None => (target.clone(), HashMap::new()),
+        };
+
+        let mut raw_bytes = format!("{} {} {}\r\n", method, target, version).into_bytes();
+        for (name, value) in &header_lines {
+            raw_bytes.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        raw_bytes.extend_from_slice(b"\r\n");
+        raw_bytes.extend_from_slice(&self.body);
+        let request_id = header_lines
+            .iter()
+            .find(|(name, _)| name == "x-request-id")
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+
+        let (raw, raw_bytes) = raw_parts(raw_bytes);
+        Request {
+            method,
+            path,
+            version,
+            raw,
+            headers: fold_headers(&header_lines),
+            header_lines,
+            query,
+            body: self.body,
+            trailers: HashMap::new(),
+            body_reader: Mutex::new(None),
+            path_params: Params::default(),
+            route_meta: Extensions::new(),
+            named_routes: NamedRoutes::default(),
+            assets: Assets::default(),
+            extensions: Extensions::new(),
+            local_addr: None,
+            remote_addr: self.remote_addr,
+            client_ip: self.remote_addr.map(|addr| addr.ip()),
+            raw_bytes,
+            request_id,
+            deadline: self.deadline,
+        }
+    }
+}
+
+// Parse `key=value&...` as sent in query strings and form bodies, decoding
+// both sides. Pairs without `=` are skipped; a repeated key keeps its last
+// value.
+pub(crate) fn parse_urlencoded(data: &[u8]) -> HashMap<String, String> {
+    urlencoded_pairs(data).into_iter().collect()
+}
+
+// Every pair in order, repeated keys included
+pub(crate) fn urlencoded_pairs(data: &[u8]) -> Vec<(String, String)> {
     let s = String::from_utf8_lossy(data);
     s.split('&').filter_map(|pair| {
         let mut parts = pair.splitn(2, '=');
         match (parts.next(), parts.next()) {
-            (Some(k), Some(v)) => Some((k.to_string(), v.to_string())),
+            (Some(k), Some(v)) => Some((form_decode(k), form_decode(v))),
             _ => None,
         }
     }).collect()
-}
\ No newline at end of file
+}
+
+// Values of `key` among the pairs in `data`, in order
+fn values_of(data: &[u8], key: &str) -> Vec<String> {
+    urlencoded_pairs(data)
+        .into_iter()
+        .filter(|(k, _)| k == key)
+        .map(|(_, v)| v)
+        .collect()
+}
+
+// `raw` and `raw_bytes` for a received message: valid UTF-8 becomes `raw`
+// without a copy, leaving `raw_bytes` empty; anything else is kept as is
+// next to a lossy `raw`
+pub(crate) fn raw_parts(message: Vec<u8>) -> (String, Vec<u8>) {
+    match String::from_utf8(message) {
+        Ok(raw) => (raw, Vec::new()),
+        Err(e) => {
+            let message = e.into_bytes();
+            (String::from_utf8_lossy(&message).into_owned(), message)
+        }
+    }
+}
+
+// One entry per header name, repeated fields joined into a list as
+// RFC 9110 section 5.3 allows (cookies with "; ", the rest with ", ")
+pub(crate) fn fold_headers(lines: &[(String, String)]) -> HashMap<String, String> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (name, value) in lines {
+        match headers.get_mut(name) {
+            Some(existing) => {
+                existing.push_str(if name == "cookie" { "; " } else { ", " });
+                existing.push_str(value);
+            }
+            None => {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    headers
+}
+
+// Split an absolute-form target ("http://host/path?q") into authority and
+// origin-form; other targets pass through with no authority
+pub(crate) fn split_request_target(target: &str) -> (Option<String>, String) {
+    let scheme_len = if target.get(..7).is_some_and(|s| s.eq_ignore_ascii_case("http://")) {
+        7
+    } else if target.get(..8).is_some_and(|s| s.eq_ignore_ascii_case("https://")) {
+        8
+    } else {
+        return (None, target.to_string());
+    };
+    let rest = &target[scheme_len..];
+    let (authority, origin) = match rest.find(['/', '?']) {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, ""),
+    };
+    let origin = if origin.starts_with('/') {
+        origin.to_string()
+    } else {
+        format!("/{}", origin)
+    };
+    (Some(authority.to_string()), origin)
+}
@@ -1,40 +1,632 @@
-use std::collections::HashMap;
+use crate::concurrency::content_etag;
+use crate::cookie::Cookie;
+use crate::headers::Headers;
+use crate::http_date::http_date;
+use crate::static_files::get_mime_type;
+
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+// Hardening headers with a safe value for most sites: no MIME sniffing,
+// no framing by other sites, and only the origin in cross-site referrers
+pub(crate) const SECURITY_HEADERS: &[(&str, &str)] = &[
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "DENY"),
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+];
 
 pub struct Response {
     pub status_code: u16,
+    // Unused when the body is streamed
     pub body: Vec<u8>,
     pub content_type: String,
-    pub headers: HashMap<String, String>,
+    pub headers: Headers,
+    pub(crate) stream: Option<Arc<BodyStream>>,
+    // Made from a rake::Error, for the server's error_renderer
+    pub(crate) from_error: bool,
+}
+
+// A body read while it is being sent
+pub(crate) struct BodyStream {
+    body: Mutex<StreamBody>,
+    len: Option<u64>,
+}
+
+enum StreamBody {
+    // Taken when the response is sent
+    Reader(Option<Box<dyn Read + Send>>),
+    // Read into memory when the response was cloned, so every copy sends it
+    Buffered(Arc<[u8]>),
+}
+
+impl BodyStream {
+    fn buffer(&self) {
+        let mut body = self.body.lock().unwrap();
+        if let StreamBody::Reader(Some(reader)) = &mut *body {
+            let mut data = Vec::new();
+            if let Err(e) = reader.read_to_end(&mut data) {
+                eprintln!("Reading a streamed body to clone it failed: {}", e);
+            }
+            *body = StreamBody::Buffered(data.into());
+        }
+    }
+
+    fn reader(&self) -> Option<Box<dyn Read + Send>> {
+        match &mut *self.body.lock().unwrap() {
+            StreamBody::Reader(reader) => reader.take(),
+            StreamBody::Buffered(data) => Some(Box::new(io::Cursor::new(Arc::clone(data)))),
+        }
+    }
+}
+
+// A streamed body is read into memory first, so the clone and the original
+// both send all of it. Don't clone one that never ends, like an SSE stream.
+impl Clone for Response {
+    fn clone(&self) -> Self {
+        if let Some(stream) = &self.stream {
+            stream.buffer();
+        }
+        Self {
+            status_code: self.status_code,
+            body: self.body.clone(),
+            content_type: self.content_type.clone(),
+            headers: self.headers.clone(),
+            stream: self.stream.clone(),
+            from_error: self.from_error,
+        }
+    }
+}
+
+// Adapts an iterator of chunks for Response::chunks
+struct ChunkReader<I> {
+    chunks: I,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for ChunkReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.current.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
 }
 
 impl Response {
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder::default()
+    }
+
     pub fn new(status_code: u16, body: Vec<u8>, content_type: &str) -> Self {
         Self {
             status_code,
             body,
             content_type: content_type.to_string(),
-            headers: HashMap::new(),
+            headers: Headers::new(),
+            stream: None,
+            from_error: false,
+        }
+    }
+
+    // 200 whose body is copied from `reader` as it is sent, e.g. a large
+    // export, without holding it in memory. The length isn't known up
+    // front, so it goes out with Transfer-Encoding: chunked (to HTTP/1.0
+    // clients, ended by closing the connection).
+    pub fn stream(reader: impl Read + Send + 'static, content_type: &str) -> Self {
+        Self::streamed(reader, None, content_type)
+    }
+
+    // Same with the length known, so it is sent as Content-Length. The
+    // reader must produce exactly `len` bytes.
+    pub fn stream_sized(reader: impl Read + Send + 'static, len: u64, content_type: &str) -> Self {
+        Self::streamed(reader, Some(len), content_type)
+    }
+
+    // 200 whose body is each chunk in turn, produced as it is sent
+    pub fn chunks<I>(chunks: I, content_type: &str) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        let reader = ChunkReader {
+            chunks: chunks.into_iter(),
+            current: Vec::new(),
+            pos: 0,
+        };
+        Self::streamed(reader, None, content_type)
+    }
+
+    fn streamed(reader: impl Read + Send + 'static, len: Option<u64>, content_type: &str) -> Self {
+        let mut response = Self::new(200, Vec::new(), content_type);
+        response.stream = Some(Arc::new(BodyStream {
+            body: Mutex::new(StreamBody::Reader(Some(Box::new(reader)))),
+            len,
+        }));
+        response
+    }
+
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // Drop the body, streamed or not, e.g. when turning a 200 into a 304
+    pub(crate) fn clear_body(&mut self) {
+        self.body = Vec::new();
+        self.stream = None;
+    }
+
+    // 200 text/html; charset=utf-8
+    pub fn html(html: &str) -> Self {
+        Self::builder().html(html).build()
+    }
+
+    // 200 text/plain; charset=utf-8
+    pub fn text(text: &str) -> Self {
+        Self::builder().text(text).build()
+    }
+
+    // 302 Found to `location`
+    pub fn redirect(location: &str) -> Self {
+        Self::redirect_with(location, 302)
+    }
+
+    // Redirect with a chosen 3xx status; panics on any other
+    pub fn redirect_with(location: &str, status: u16) -> Self {
+        assert!((300..400).contains(&status), "redirect to {}: {} is not a 3xx status", location, status);
+        Self::new(status, Vec::new(), "text/plain").with_header("Location", location)
+    }
+
+    // 308: moved for good, and the client repeats the same method and body
+    pub fn permanent_redirect(location: &str) -> Self {
+        Self::redirect_with(location, 308)
+    }
+
+    // 307: the same method and body again at `location`, this time only
+    pub fn temporary_redirect(location: &str) -> Self {
+        Self::redirect_with(location, 307)
+    }
+
+    // 303: GET `location`, e.g. after a form POST so reloading the result
+    // doesn't submit it again
+    pub fn see_other(location: &str) -> Self {
+        Self::redirect_with(location, 303)
+    }
+
+    // 404 with a short text body. Registered error handlers are not used;
+    // return this only when the handler's own page is what's wanted.
+    pub fn not_found() -> Self {
+        Self::builder().status(404).text("Not Found").build()
+    }
+
+    pub fn no_content() -> Self {
+        Self::new(204, Vec::new(), "text/plain")
+    }
+
+    // 200 with the file's contents (streamed), its type guessed from the extension as
+    // for static files, and Last-Modified so conditional GETs can get 304.
+    // 404 if there is no such file, 403 if it can't be read.
+    pub fn file(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => return Self::not_found(),
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::not_found(),
+            Err(_) => return Self::builder().status(403).text("Forbidden").build(),
+        };
+        // Read while sending, so large downloads don't sit in memory
+        let mut response = Self::stream_sized(file.take(metadata.len()), metadata.len(), get_mime_type(path));
+        if let Ok(modified) = metadata.modified() {
+            response = response.with_header("Last-Modified", &http_date(modified));
         }
+        response
     }
 
+    // Replaces any earlier value of the same header
     pub fn with_header(mut self, key: &str, value: &str) -> Self {
-        self.headers.insert(key.to_string(), value.to_string());
+        self.headers.insert(key, value);
+        self
+    }
+
+    // The SECURITY_HEADERS this response doesn't set itself. See also
+    // SimpleHttpServer::security_headers, for every response.
+    pub fn with_security_headers(mut self) -> Self {
+        for (key, value) in SECURITY_HEADERS {
+            if !self.headers.contains_key(key) {
+                self.headers.insert(*key, *value);
+            }
+        }
+        self
+    }
+
+    // Adds another value, e.g. a second Link or Vary line
+    pub fn append_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.append(key, value);
         self
     }
 
+    // Adds a Set-Cookie header; each cookie set goes out on its own line
+    pub fn set_cookie(self, cookie: Cookie) -> Self {
+        self.append_header("Set-Cookie", &cookie.to_string())
+    }
+
+    // ETag hashed from the body, so a client polling with If-None-Match
+    // gets a 304 while it is unchanged (see SimpleHttpServer::conditional_get).
+    // A streamed body isn't known up front and gets none.
+    pub fn with_etag_of_body(self) -> Self {
+        if self.stream.is_some() {
+            return self;
+        }
+        let etag = content_etag(&self.body);
+        self.with_header("ETag", &etag)
+    }
+
+    // Content-Disposition: attachment, so browsers save the body as
+    // `filename` instead of showing it. A non-ASCII name is also sent
+    // UTF-8 encoded in filename* (RFC 6266), with `_` in its place in the
+    // plain filename for clients that don't read that.
+    pub fn as_attachment(self, filename: &str) -> Self {
+        let filename = filename.replace(['\r', '\n'], "");
+        let mut fallback = String::new();
+        for c in filename.chars() {
+            match c {
+                '"' | '\\' => {
+                    fallback.push('\\');
+                    fallback.push(c);
+                }
+                ' '..='~' => fallback.push(c),
+                _ => fallback.push('_'),
+            }
+        }
+        let mut value = format!("attachment; filename=\"{}\"", fallback);
+        if !filename.is_ascii() {
+            value.push_str("; filename*=UTF-8''");
+            for b in filename.bytes() {
+                // attr-char (RFC 8187)
+                if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                    value.push(b as char);
+                } else {
+                    value.push_str(&format!("%{:02X}", b));
+                }
+            }
+        }
+        self.with_header("Content-Disposition", &value)
+    }
+
+    // Tells the client to drop the cookie `name` set with Path=/, like the
+    // session cookie. One set with another Path or Domain is only removed
+    // by a cookie with the same ones: set_cookie(Cookie::new(name, "")
+    // .path(path).max_age(0)).
+    pub fn delete_cookie(self, name: &str) -> Self {
+        self.set_cookie(Cookie::new(name, "").path("/").max_age(0).expires(UNIX_EPOCH))
+    }
+
+    // Case-insensitive header lookup; the first value if there are several
+    pub fn header(&self, key: &str) -> Option<&String> {
+        self.headers.get(key)
+    }
+
     pub fn to_http(&self) -> Vec<u8> {
+        self.to_http_as("HTTP/1.1")
+    }
+
+    // Status line (with `version`), headers and body; for a streamed
+    // response only the status line and headers
+    pub(crate) fn to_http_as(&self, version: &str) -> Vec<u8> {
+        let mut response = self.head(version);
+        if self.has_content() && self.stream.is_none() {
+            response.extend(&self.body);
+        }
+        response
+    }
+
+    // Write the whole response, copying a streamed body from its reader.
+    // The answer to a HEAD request is the head alone, framed as the GET
+    // response would be, so the client doesn't wait for a body.
+    pub(crate) fn write_to(&self, out: &mut impl Write, version: &str, head_request: bool) -> io::Result<()> {
+        if head_request {
+            out.write_all(&self.head(version))?;
+            return out.flush();
+        }
+        out.write_all(&self.to_http_as(version))?;
+        if let Some(stream) = &self.stream
+            && self.has_content()
+            && let Some(mut reader) = stream.reader()
+        {
+            if self.is_chunked(version) {
+                write_chunked(&mut reader, out)?;
+            } else {
+                io::copy(&mut reader, out)?;
+            }
+        }
+        out.flush()
+    }
+
+    // A streamed body of unknown length to an HTTP/1.1 client
+    fn is_chunked(&self, version: &str) -> bool {
+        version != "HTTP/1.0" && self.stream.as_ref().is_some_and(|stream| stream.len.is_none())
+    }
+
+    // 1xx, 204 and 304 responses never carry content (RFC 9110 section 6.4.1)
+    pub(crate) fn has_content(&self) -> bool {
+        !matches!(self.status_code, 100..=199 | 204 | 304)
+    }
+
+    // The reader of a streamed body, once
+    pub(crate) fn body_reader(&self) -> Option<Box<dyn Read + Send>> {
+        self.stream.as_ref()?.reader()
+    }
+
+    fn head(&self, version: &str) -> Vec<u8> {
+        let version = if version == "HTTP/1.0" { "HTTP/1.0" } else { "HTTP/1.1" };
         let mut header = format!(
-            "HTTP/1.1 {} OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            "{} {} {}\r\n",
+            version,
             self.status_code,
-            self.content_type,
-            self.body.len()
+            reason_phrase(self.status_code)
         );
-        for (k, v) in &self.headers {
+        for (k, v) in self.fields(self.is_chunked(version)) {
             header.push_str(&format!("{}: {}\r\n", k, v));
         }
         header.push_str("\r\n");
-        let mut response = header.into_bytes();
-        response.extend(&self.body);
+        header.into_bytes()
+    }
+
+    // Header fields for HTTP/2: lower-case names, and none of the
+    // connection-specific ones it forbids (RFC 9113 section 8.2.2)
+    pub(crate) fn h2_fields(&self) -> Vec<(String, String)> {
+        self.fields(false)
+            .into_iter()
+            .map(|(k, v)| (k.to_ascii_lowercase(), v.to_string()))
+            .filter(|(k, _)| {
+                !matches!(
+                    k.as_str(),
+                    "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade"
+                )
+            })
+            .collect()
+    }
+
+    // Content-Type, framing and the handler's headers, in the order sent.
+    // `chunked` announces a streamed body of unknown length as chunked.
+    fn fields(&self, chunked: bool) -> Vec<(&str, Cow<'_, str>)> {
+        let mut fields = Vec::new();
+        let has_content = self.has_content();
+        if has_content {
+            fields.push(("Content-Type", Cow::Borrowed(self.content_type.as_str())));
+            let len = match &self.stream {
+                Some(stream) => stream.len,
+                None => Some(self.body.len() as u64),
+            };
+            if let Some(len) = len {
+                fields.push(("Content-Length", Cow::Owned(len.to_string())));
+            } else if chunked {
+                fields.push(("Transfer-Encoding", Cow::Borrowed("chunked")));
+            }
+        }
+        for (k, v) in &self.headers {
+            let is_framing = k.eq_ignore_ascii_case("content-length") || k.eq_ignore_ascii_case("transfer-encoding");
+            // A streamed body's framing was worked out above
+            if (!has_content && (is_framing || k.eq_ignore_ascii_case("content-type")))
+                || (self.stream.is_some() && is_framing)
+            {
+                continue;
+            }
+            fields.push((k.as_str(), Cow::Borrowed(v.as_str())));
+        }
+        fields
+    }
+}
+
+// Builds a Response step by step:
+//
+//     Response::builder()
+//         .status(201)
+//         .header("Location", &url)
+//         .json(&item)
+//         .build()
+//
+// Status 200 and an empty text/plain body unless set.
+pub struct ResponseBuilder {
+    response: Response,
+}
+
+impl Default for ResponseBuilder {
+    fn default() -> Self {
+        Self {
+            response: Response::new(200, Vec::new(), "text/plain"),
+        }
+    }
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, code: u16) -> Self {
+        self.response.status_code = code;
+        self
+    }
+
+    // Replaces an earlier value of the same header
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.response.headers.insert(key, value);
+        self
+    }
+
+    // Adds another value of a header that may repeat
+    pub fn append_header(mut self, key: &str, value: &str) -> Self {
+        self.response.headers.append(key, value);
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.response.content_type = content_type.to_string();
+        self
+    }
+
+    // Body as is; the content type stays what was set
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.response.body = body.into();
+        self
+    }
+
+    // Body and text/plain; charset=utf-8
+    pub fn text(self, text: &str) -> Self {
+        self.content_type("text/plain; charset=utf-8").body(text)
+    }
+
+    // Body and text/html; charset=utf-8
+    pub fn html(self, html: &str) -> Self {
+        self.content_type("text/html; charset=utf-8").body(html)
+    }
+
+    pub fn build(self) -> Response {
+        self.response
+    }
+}
+
+// What a handler can return instead of building a Response itself:
+//
+//     server.get("/ping", |_, _| "pong");
+//     server.post("/items", |req, _| -> Result<Response, JsonError> {
+//         let item: Item = req.json()?;
+//         Ok(Response::json(&save(item)))
+//     });
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+// 200 text/plain; charset=utf-8
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::builder().content_type("text/plain; charset=utf-8").body(self).build()
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        Response::text(self)
+    }
+}
+
+// The status with a text/plain body, e.g. (404, format!("no user {}", id))
+impl IntoResponse for (u16, String) {
+    fn into_response(self) -> Response {
+        let (status, text) = self;
+        let mut response = text.into_response();
+        response.status_code = status;
         response
     }
 }
+
+impl IntoResponse for (u16, &'static str) {
+    fn into_response(self) -> Response {
+        (self.0, self.1.to_string()).into_response()
+    }
+}
+
+// 200 application/octet-stream
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        Response::new(200, self, "application/octet-stream")
+    }
+}
+
+// Whichever side it is, so errors that know their response (JsonError,
+// ParamError, ...) can be returned with `?`
+impl<R: IntoResponse, E: IntoResponse> IntoResponse for Result<R, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(ok) => ok.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+// Copy `reader` to `out` as chunks, each one read() of the reader, then
+// the terminating empty chunk
+fn write_chunked(reader: &mut impl Read, out: &mut impl Write) -> io::Result<()> {
+    let mut buffer = vec![0; 16 * 1024];
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            return out.write_all(b"0\r\n\r\n");
+        }
+        out.write_all(format!("{:x}\r\n", n).as_bytes())?;
+        out.write_all(&buffer[..n])?;
+        out.write_all(b"\r\n")?;
+        // Let the client see each piece as soon as it is produced
+        out.flush()?;
+    }
+}
+
+pub(crate) fn reason_phrase(code: u16) -> &'static str {
+    match code {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        422 => "Unprocessable Content",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => match code / 100 {
+            1 => "Informational",
+            2 => "Success",
+            3 => "Redirection",
+            4 => "Client Error",
+            _ => "Server Error",
+        },
+    }
+}
@@ -1,60 +1,1030 @@
+use crate::extensions::Extensions;
+use crate::mime::Mime;
+use crate::regex::Regex;
 use crate::request::Request;
-use crate::response::Response;
+use crate::response::{IntoResponse, Response};
+use crate::server::AfterMiddleware;
+use crate::urls::{self, NamedRoutes};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
 
-pub type HandlerFn = fn(&Request, &HashMap<String, String>) -> Response;
+// Handlers are closures so they can carry state (pools, config, counters)
+pub type HandlerFn = Arc<dyn Fn(&Request, &Params) -> Response + Send + Sync>;
+// Runs before one route's handler; returning a response skips the handler
+pub type RouteMiddleware = Arc<dyn Fn(&Request) -> Option<Response> + Send + Sync>;
+
+// Must return true for its route to match; otherwise matching moves on to
+// the next candidate as if the path hadn't fit
+pub type RouteGuard = Arc<dyn Fn(&Request) -> bool + Send + Sync>;
+
+// Method recorded for routes registered with any()
+pub(crate) const ANY_METHOD: &str = "*";
+
+// Parameter types usable as <name:type> in a route pattern
+const PARAM_TYPES: &[&str] = &["int", "uint", "float", "str", "uuid"];
+
+// Path parameters captured by the matched route. Derefs to the raw strings;
+// the typed getters parse on access (already validated for typed segments).
+#[derive(Clone, Debug, Default)]
+pub struct Params(HashMap<String, String>);
+
+impl Deref for Params {
+    type Target = HashMap<String, String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<HashMap<String, String>> for Params {
+    fn from(map: HashMap<String, String>) -> Self {
+        Self(map)
+    }
+}
+
+impl Params {
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        self.get_as(name)
+    }
+
+    pub fn get_uint(&self, name: &str) -> Option<u64> {
+        self.get_as(name)
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        self.get_as(name)
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|s| s.as_str())
+    }
+
+    // Parse a parameter into any FromStr type
+    pub fn get_as<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.0.get(name)?.parse().ok()
+    }
+}
 
 #[derive(Clone)]
 pub struct Route {
     pub method: String,
     pub path: String,
     pub handler: HandlerFn,
+    // Patterns parameters must fully match for the route to apply
+    pub(crate) constraints: Vec<(String, Regex)>,
+    // Per-route middleware, run around the handler only for this route
+    pub(crate) before: Vec<RouteMiddleware>,
+    pub(crate) after: Vec<AfterMiddleware>,
+    pub(crate) guards: Vec<RouteGuard>,
+    pub(crate) meta: Extensions,
+    pub(crate) stream_body: bool,
+    // Media types (or ranges like image/*) the body may have; empty for any
+    pub(crate) consumes: Vec<Mime>,
+    // Set by route_named, for url_for and introspection
+    pub(crate) name: Option<String>,
+    // Answer 428 when the request has no If-Match
+    pub(crate) require_if_match: bool,
+    // Concurrent identical GETs share one handler run
+    pub(crate) coalesce: bool,
+    // A GET route also answers HEAD (the body is dropped when sending),
+    // unless there is a HEAD route for the same pattern. Kept up to date
+    // as routes are added.
+    serves_head: bool,
+    // Rank of each segment, compared left to right to order overlapping routes
+    precedence: Vec<u8>,
+    segments: Vec<Segment>,
+}
+
+// A registered route as reported by Router::routes()
+#[derive(Clone, Debug)]
+pub struct RouteInfo {
+    // Upper-case method, or "*" for any()
+    pub method: String,
+    pub pattern: String,
+    pub name: Option<String>,
+    // (name, type) of each parameter in order; a catch-all has type "path"
+    pub params: Vec<(String, String)>,
+    // Attached with RouteHandle::meta
+    pub meta: Extensions,
+    // Set with RouteHandle::require_if_match
+    pub requires_if_match: bool,
+    // Set with RouteHandle::coalesce
+    pub coalesced: bool,
+}
+
+// One `/`-separated piece of a route pattern, parsed at registration
+#[derive(Clone, Debug)]
+enum Segment {
+    Static(String),
+    Param { name: String, kind: String },
+    // `<name..>`: the rest of the path
+    Rest(String),
+}
+
+// Radix-style index over route patterns: static segments are looked up by
+// ASCII-lowercased key, parameters hang off a single child. Lookup walks
+// only the branches that fit the path and yields candidate routes, which
+// are then checked (method, types, constraints) most specific first.
+#[derive(Clone, Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    param: Option<Box<Node>>,
+    // Routes whose pattern ends here
+    routes: Vec<usize>,
+    // Routes ending in a catch-all at this position
+    rest: Vec<usize>,
+}
+
+impl Node {
+    fn insert(&mut self, segments: &[Segment], index: usize) {
+        match segments.first() {
+            None => self.routes.push(index),
+            Some(Segment::Rest(_)) => self.rest.push(index),
+            Some(Segment::Static(key)) => self
+                .statics
+                .entry(key.to_ascii_lowercase())
+                .or_default()
+                .insert(&segments[1..], index),
+            Some(Segment::Param { .. }) => self
+                .param
+                .get_or_insert_with(Default::default)
+                .insert(&segments[1..], index),
+        }
+    }
+
+    fn candidates(&self, parts: &[&str], out: &mut Vec<usize>) {
+        let Some((first, rest)) = parts.split_first() else {
+            out.extend(&self.routes);
+            return;
+        };
+        if !self.rest.is_empty() && parts.iter().any(|p| !p.is_empty()) {
+            out.extend(&self.rest);
+        }
+        let key = if first.bytes().any(|b| b.is_ascii_uppercase()) {
+            Cow::Owned(first.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(*first)
+        };
+        if let Some(child) = self.statics.get(key.as_ref()) {
+            child.candidates(rest, out);
+        }
+        if let Some(child) = &self.param {
+            child.candidates(rest, out);
+        }
+    }
+}
+
+impl Route {
+    // Whether the request's body is of a type declared with consumes()
+    pub(crate) fn consumes_request(&self, req: &Request) -> bool {
+        if self.consumes.is_empty() {
+            return true;
+        }
+        match req.content_type() {
+            Some(content_type) => self.consumes.iter().any(|range| range.covers(&content_type)),
+            None => {
+                let has_body = req.content_length().is_some_and(|len| len > 0) || req.header("transfer-encoding").is_some();
+                req.header("content-type").is_none() && !has_body
+            }
+        }
+    }
+
+    // Metadata attached with RouteHandle::meta
+    pub fn meta(&self) -> &Extensions {
+        &self.meta
+    }
+
+    // Parameters if `parts` fits this route's types and constraints. The
+    // trie has already checked the static segments (ignoring case) and the
+    // segment count.
+    fn capture(&self, parts: &[&str]) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(_) => {}
+                Segment::Param { name, kind } => {
+                    if !matches_type(kind, parts[i]) {
+                        return None;
+                    }
+                    params.insert(name.clone(), parts[i].to_string());
+                }
+                Segment::Rest(name) => {
+                    params.insert(name.clone(), parts[i..].join("/"));
+                }
+            }
+        }
+        let satisfied = self
+            .constraints
+            .iter()
+            .all(|(name, regex)| params.get(name).is_some_and(|v| regex.is_full_match(v)));
+        satisfied.then_some(params)
+    }
+
+    // Same method and the same pattern up to parameter names, with nothing
+    // (guards, constraints) to tell the two apart
+    fn conflicts_with(&self, other: &Route) -> bool {
+        let unconditional = |route: &Route| route.guards.is_empty() && route.constraints.is_empty();
+        let same_shape = self.segments.len() == other.segments.len()
+            && self.segments.iter().zip(&other.segments).all(|pair| match pair {
+                (Segment::Static(a), Segment::Static(b)) => a == b,
+                (Segment::Param { kind: a, .. }, Segment::Param { kind: b, .. }) => a == b,
+                (Segment::Rest(_), Segment::Rest(_)) => true,
+                _ => false,
+            });
+        self.method == other.method
+            && same_shape
+            && has_trailing_slash(&self.path) == has_trailing_slash(&other.path)
+            && unconditional(self)
+            && unconditional(other)
+    }
+
+    // Whether the static segments match with the pattern's exact case
+    fn case_matches(&self, parts: &[&str]) -> bool {
+        self.segments
+            .iter()
+            .zip(parts)
+            .all(|(segment, part)| !matches!(segment, Segment::Static(s) if s != part))
+    }
+
+    // `parts` with the static segments spelled as in the pattern
+    fn recase(&self, parts: &[&str], trailing_slash: bool) -> String {
+        let parts: Vec<&str> = parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| match self.segments.get(i) {
+                Some(Segment::Static(s)) => s.as_str(),
+                _ => part,
+            })
+            .collect();
+        let path = format!("/{}", parts.join("/"));
+        if trailing_slash && path != "/" {
+            path + "/"
+        } else {
+            path
+        }
+    }
+}
+
+pub enum RouteMatch<'a> {
+    Found(&'a Route, Params),
+    // The path is routed, but only for these methods
+    MethodNotAllowed(Vec<String>),
+    // Only the trailing slash differs and the policy is to redirect there
+    Redirect(String),
+    NotFound,
+}
+
+// How a trailing slash on the request path relates to the route pattern
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    // "/foo" and "/foo/" both match either pattern
+    #[default]
+    Ignore,
+    // The request must have a trailing slash exactly when the pattern does
+    Strict,
+    // Like Strict, but a request that only differs in the slash is sent a
+    // 301 (308 for non-GET/HEAD) to the pattern's form
+    Redirect,
+}
+
+// Whether "/About" matches a route registered as "/about". Only the static
+// segments are compared; parameter values keep the case they were sent in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathCase {
+    #[default]
+    Sensitive,
+    // ASCII letters match regardless of case
+    Insensitive,
+    // Like Insensitive, but the request is sent a 301 (308 for
+    // non-GET/HEAD) to the pattern's spelling
+    Redirect,
 }
 
 #[derive(Clone)]
 pub struct Router {
     routes: Vec<Route>,
+    root: Node,
+    trailing_slash: TrailingSlash,
+    path_case: PathCase,
+    decode_path: bool,
+    pub(crate) names: NamedRoutes,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Router {
     pub fn new() -> Self {
-        Self { routes: Vec::new() }
+        Self {
+            routes: Vec::new(),
+            root: Node::default(),
+            trailing_slash: TrailingSlash::default(),
+            path_case: PathCase::default(),
+            decode_path: true,
+            names: NamedRoutes::default(),
+        }
+    }
+
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
     }
 
-    pub fn add_route(&mut self, method: &str, path: &str, handler: HandlerFn) {
-        self.routes.push(Route {
+    pub fn set_path_case(&mut self, policy: PathCase) {
+        self.path_case = policy;
+    }
+
+    // Whether path segments are percent-decoded before matching, so
+    // `/hello/J%C3%BCrgen` gives name "Jürgen" (the default). Turn off to
+    // match and capture the raw encoded form.
+    pub fn set_decode_path(&mut self, decode: bool) {
+        self.decode_path = decode;
+    }
+
+    // Panics on an unknown <name:type>, since the route could never match
+    pub fn add_route<H, R>(&mut self, method: &str, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.add_route_with(method, path, &[], handler)
+    }
+
+    // Match every method for `path`, e.g. a webhook receiver
+    pub fn any<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.add_route(ANY_METHOD, path, handler)
+    }
+
+    // Like add_route, but each named parameter must also fully match its
+    // regex, e.g. &[("date", r"\d{4}-\d{2}-\d{2}")]; otherwise matching
+    // moves on to less specific routes. Panics on an invalid regex or a name that
+    // isn't a parameter of `path`.
+    pub fn add_route_with<H, R>(
+        &mut self,
+        method: &str,
+        path: &str,
+        constraints: &[(&str, &str)],
+        handler: H,
+    ) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let names: Vec<&str> = path
+            .split('/')
+            .filter_map(|seg| seg.strip_prefix('<')?.strip_suffix('>'))
+            .map(|param| param.trim_end_matches("..").split(':').next().unwrap_or(param))
+            .collect();
+        let constraints: Vec<(String, Regex)> = constraints
+            .iter()
+            .map(|(name, pattern)| {
+                assert!(
+                    names.contains(name),
+                    "constraint on `{}`, which is not a parameter of route {}",
+                    name,
+                    path
+                );
+                let regex = Regex::new(pattern).unwrap_or_else(|e| panic!("route {}: {}", path, e));
+                (name.to_string(), regex)
+            })
+            .collect();
+        let segments = parse_segments(path);
+        let precedence = segments
+            .iter()
+            .map(|segment| segment_rank(segment, &constraints))
+            .collect();
+        self.push(Route {
             method: method.to_uppercase(),
             path: path.to_string(),
-            handler,
+            handler: Arc::new(move |req: &Request, params: &Params| handler(req, params).into_response()),
+            constraints,
+            before: Vec::new(),
+            after: Vec::new(),
+            guards: Vec::new(),
+            meta: Extensions::new(),
+            stream_body: false,
+            consumes: Vec::new(),
+            name: None,
+            require_if_match: false,
+            coalesce: false,
+            serves_head: false,
+            precedence,
+            segments,
         });
+        RouteHandle {
+            route: self.routes.last_mut().unwrap(),
+        }
     }
 
-    pub fn find(&self, method: &str, path: &str) -> Option<(&HandlerFn, HashMap<String, String>)> {
-        for route in &self.routes {
-            if route.method == method.to_uppercase() {
-                if let Some(params) = match_route(&route.path, path) {
-                    return Some((&route.handler, params));
+    pub fn find(&self, method: &str, path: &str) -> Option<(&HandlerFn, Params)> {
+        match self.lookup(method, path) {
+            RouteMatch::Found(route, params) => Some((&route.handler, params)),
+            _ => None,
+        }
+    }
+
+    // Like find, but tells a path no route knows apart from one that only
+    // has routes for other methods. Guarded routes never match, since there
+    // is no request to check; see lookup_request.
+    pub fn lookup(&self, method: &str, path: &str) -> RouteMatch<'_> {
+        self.resolve(method, path, None)
+    }
+
+    // Like lookup, but routes with guards match if every guard accepts `req`
+    pub fn lookup_request(&self, req: &Request) -> RouteMatch<'_> {
+        self.resolve(&req.method, &req.path, Some(req))
+    }
+
+    fn resolve(&self, method: &str, path: &str, req: Option<&Request>) -> RouteMatch<'_> {
+        let method = method.to_uppercase();
+        let raw_parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+        // Decoded per segment, so an encoded `/` (%2F) stays inside its segment
+        let decoded: Vec<Cow<str>> = if self.decode_path {
+            raw_parts.iter().map(|part| urls::percent_decode(part)).collect()
+        } else {
+            raw_parts.iter().map(|part| Cow::Borrowed(*part)).collect()
+        };
+        let parts: Vec<&str> = decoded.iter().map(|part| part.as_ref()).collect();
+        let mut candidates = Vec::new();
+        self.root.candidates(&parts, &mut candidates);
+        // Most specific first, then the one with more guards, ties going to
+        // the first registered
+        candidates.sort_by(|&a, &b| {
+            let (a_route, b_route) = (&self.routes[a], &self.routes[b]);
+            b_route
+                .precedence
+                .cmp(&a_route.precedence)
+                .then(b_route.guards.len().cmp(&a_route.guards.len()))
+                .then(a.cmp(&b))
+        });
+
+        let mut allowed: Vec<String> = Vec::new();
+        let mut redirect = None;
+        for index in candidates {
+            let route = &self.routes[index];
+            let Some(params) = route.capture(&parts) else {
+                continue;
+            };
+            let guarded_out = match req {
+                Some(req) => !route.guards.iter().all(|guard| guard(req)),
+                None => !route.guards.is_empty(),
+            };
+            if guarded_out {
+                continue;
+            }
+            let method_ok = route.method == method
+                || route.method == ANY_METHOD
+                || (method == "HEAD" && route.serves_head);
+            let slash_ok = self.trailing_slash == TrailingSlash::Ignore
+                || matches!(route.segments.last(), Some(Segment::Rest(_)))
+                || has_trailing_slash(path) == has_trailing_slash(&route.path);
+            let case_ok = self.path_case == PathCase::Insensitive || route.case_matches(&parts);
+            if !case_ok && self.path_case == PathCase::Sensitive {
+                continue;
+            }
+            if !slash_ok || !case_ok {
+                let fixable = slash_ok || self.trailing_slash == TrailingSlash::Redirect;
+                if method_ok && fixable && redirect.is_none() {
+                    let mut location = if case_ok {
+                        path.to_string()
+                    } else {
+                        route.recase(&raw_parts, has_trailing_slash(path))
+                    };
+                    if !slash_ok {
+                        location = toggle_trailing_slash(&location);
+                    }
+                    redirect = Some(location);
                 }
+                continue;
+            }
+            if method_ok {
+                return RouteMatch::Found(route, Params(params));
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method.clone());
             }
+            if route.method == "GET" && !allowed.iter().any(|m| m == "HEAD") {
+                allowed.push("HEAD".to_string());
+            }
+        }
+        if let Some(location) = redirect {
+            RouteMatch::Redirect(location)
+        } else if allowed.is_empty() {
+            RouteMatch::NotFound
+        } else {
+            allowed.sort();
+            RouteMatch::MethodNotAllowed(allowed)
         }
-        None
     }
+
+    // Register routes relative to `prefix`, e.g.
+    // let mut api = router.group("/api/v1"); api.route("GET", "/users", list_users);
+    pub fn group(&mut self, prefix: &str) -> RouteGroup<'_> {
+        RouteGroup {
+            router: self,
+            prefix: prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    // Add every route of `other`, e.g. ones contributed by a plugin. Its
+    // trailing-slash, case and decoding settings are not carried over.
+    // Panics if a route has the same method and pattern shape as an
+    // existing one and neither has guards or constraints, since one of
+    // them could never match, or if a route name is taken.
+    pub fn merge(&mut self, other: Router) {
+        for (name, pattern) in other.names.iter() {
+            self.names.register(name, pattern);
+        }
+        for route in other.routes {
+            if let Some(existing) = self.routes.iter().find(|existing| existing.conflicts_with(&route)) {
+                panic!(
+                    "cannot merge route {} {}: it conflicts with {} {}",
+                    route.method, route.path, existing.method, existing.path
+                );
+            }
+            self.push(route);
+        }
+    }
+
+    fn push(&mut self, mut route: Route) {
+        route.serves_head = route.method == "GET"
+            && !self.routes.iter().any(|other| other.method == "HEAD" && other.path == route.path);
+        if route.method == "HEAD" {
+            for other in &mut self.routes {
+                if other.method == "GET" && other.path == route.path {
+                    other.serves_head = false;
+                }
+            }
+        }
+        self.root.insert(&route.segments, self.routes.len());
+        self.routes.push(route);
+    }
+
+    // Like add_route, but linkable by name with url_for. Panics if the name
+    // is already used for a different pattern.
+    pub fn add_named_route<H, R>(&mut self, name: &str, method: &str, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.names.register(name, path);
+        let handle = self.add_route(method, path, handler);
+        handle.route.name = Some(name.to_string());
+        handle
+    }
+
+    // URL of a route named with add_named_route, its parameters filled in,
+    // e.g. url_for("user_detail", &[("id", "42")]) -> Some("/users/42").
+    // Parameters the pattern doesn't use become the query string. None if
+    // the name is unknown, a parameter is missing, or a value doesn't fit
+    // its <name:type>.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        self.names.url_for(name, params)
+    }
+
+    // Replace `{{ url_for("user_detail", id=42) }}` placeholders in a
+    // template, for template engines to call before their own
+    // substitution. Unknown routes render as an empty string.
+    pub fn expand_urls(&self, template: &str) -> String {
+        self.names.expand_urls(template)
+    }
+
+    // Every route in registration order, e.g. to print a route table at
+    // startup or assert in tests that an endpoint exists
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.routes
+            .iter()
+            .map(|route| RouteInfo {
+                method: route.method.clone(),
+                pattern: route.path.clone(),
+                name: route.name.clone(),
+                meta: route.meta.clone(),
+                requires_if_match: route.require_if_match,
+                coalesced: route.coalesce,
+                params: route
+                    .segments
+                    .iter()
+                    .filter_map(|segment| match segment {
+                        Segment::Static(_) => None,
+                        Segment::Param { name, kind } => Some((name.clone(), kind.clone())),
+                        Segment::Rest(name) => Some((name.clone(), "path".to_string())),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    // Paths of GET routes without parameters, i.e. concrete pages
+    pub(crate) fn static_get_paths(&self) -> Vec<String> {
+        self.routes
+            .iter()
+            .filter(|r| r.method == "GET" && !r.path.contains('<'))
+            .map(|r| r.path.clone())
+            .collect()
+    }
+}
+
+// Returned when registering a route, to configure it further:
+// server.route("GET", "/admin", admin).with(require_login)
+pub struct RouteHandle<'a> {
+    route: &'a mut Route,
 }
 
-// Match dynamic routes like /hello/<name>
-fn match_route(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
-    let mut params = HashMap::new();
-    let pat_parts: Vec<_> = pattern.trim_matches('/').split('/').collect();
-    let path_parts: Vec<_> = path.trim_matches('/').split('/').collect();
-    if pat_parts.len() != path_parts.len() {
-        return None;
+impl RouteHandle<'_> {
+    // Run `mw` before this route's handler; if it returns a response, that
+    // is sent instead (e.g. 401 from an auth check)
+    pub fn with<M>(self, mw: M) -> Self
+    where
+        M: Fn(&Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        self.route.before.push(Arc::new(mw));
+        self
+    }
+
+    // Run `mw` on this route's response, before the global after-middleware
+    pub fn after<M>(self, mw: M) -> Self
+    where
+        M: Fn(&Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.route.after.push(Arc::new(mw));
+        self
+    }
+
+    // Attach a typed value, e.g. a required role or doc tags, readable from
+    // handlers with req.route_meta() and from Router::routes()
+    pub fn meta<T: Send + Sync + 'static>(self, value: T) -> Self {
+        self.route.meta.insert(value);
+        self
+    }
+
+    // Don't buffer the request body; the handler reads it from
+    // req.body_reader() instead, e.g. to pipe a large upload to disk
+    pub fn stream_body(self) -> Self {
+        self.route.stream_body = true;
+        self
+    }
+
+    // Answer 415 Unsupported Media Type unless the body's Content-Type is
+    // this one (or one of those given by repeated calls); ranges like
+    // "image/*" are allowed. A request without a body or Content-Type
+    // passes. Panics if `media_type` isn't a type/subtype.
+    pub fn consumes(self, media_type: &str) -> Self {
+        let mime = Mime::parse(media_type).unwrap_or_else(|| panic!("invalid media type: {}", media_type));
+        self.route.consumes.push(mime);
+        self
+    }
+
+    // Only match requests `guard` accepts, e.g. a header check for API
+    // versioning; others fall through to the next candidate route
+    pub fn guard<G>(self, guard: G) -> Self
+    where
+        G: Fn(&Request) -> bool + Send + Sync + 'static,
+    {
+        self.route.guards.push(Arc::new(guard));
+        self
+    }
+
+    // Only match when query parameter `name` is `value`, e.g.
+    // .query("type", "user") for /search?type=user. A guard, so routes on
+    // the same path can dispatch on different values.
+    pub fn query(self, name: &str, value: &str) -> Self {
+        let (name, value) = (name.to_string(), value.to_string());
+        self.guard(move |req| req.param(&name).is_some_and(|v| *v == value))
+    }
+
+    // Reject requests without an If-Match header with 428, so clients
+    // cannot skip the check done by `check_if_match`
+    pub fn require_if_match(self) -> Self {
+        self.route.require_if_match = true;
+        self
+    }
+
+    // Concurrent identical GETs share a single handler run. Requests are
+    // identical when their host, target, Accept, Accept-Encoding,
+    // Authorization and Cookie all agree, so clients only share answers
+    // meant for them alike.
+    pub fn coalesce(self) -> Self {
+        self.route.coalesce = true;
+        self
+    }
+
+    // Only match when query parameter `name` is present, whatever its value
+    pub fn has_query(self, name: &str) -> Self {
+        let name = name.to_string();
+        self.guard(move |req| req.query.contains_key(&name))
+    }
+}
+
+// Routes registered through a group get its prefix prepended
+pub struct RouteGroup<'a> {
+    router: &'a mut Router,
+    prefix: String,
+}
+
+impl RouteGroup<'_> {
+    pub fn route<H, R>(&mut self, method: &str, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route_with(method, path, &[], handler)
+    }
+
+    pub fn get<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("POST", path, handler)
+    }
+
+    pub fn put<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("PUT", path, handler)
+    }
+
+    pub fn delete<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("DELETE", path, handler)
+    }
+
+    pub fn patch<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("PATCH", path, handler)
+    }
+
+    pub fn route_with<H, R>(
+        &mut self,
+        method: &str,
+        path: &str,
+        constraints: &[(&str, &str)],
+        handler: H,
+    ) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let full = join_path(&self.prefix, path);
+        self.router.add_route_with(method, &full, constraints, handler)
+    }
+
+    pub fn any<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(ANY_METHOD, path, handler)
+    }
+
+    pub fn route_named<H, R>(&mut self, name: &str, method: &str, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let full = join_path(&self.prefix, path);
+        self.router.add_named_route(name, method, &full, handler)
     }
-    for (pat, val) in pat_parts.iter().zip(path_parts.iter()) {
-        if pat.starts_with('<') && pat.ends_with('>') {
-            params.insert(pat[1..pat.len()-1].to_string(), val.to_string());
-        } else if pat != val {
-            return None;
+
+    // A nested group, e.g. api.group("/admin") under "/api/v1"
+    pub fn group(&mut self, prefix: &str) -> RouteGroup<'_> {
+        RouteGroup {
+            prefix: join_path(&self.prefix, prefix).trim_end_matches('/').to_string(),
+            router: self.router,
+        }
+    }
+}
+
+fn join_path(prefix: &str, path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    if path.is_empty() && prefix.is_empty() {
+        "/".to_string()
+    } else if path.is_empty() {
+        prefix.to_string()
+    } else {
+        format!("{}/{}", prefix, path)
+    }
+}
+
+fn has_trailing_slash(path: &str) -> bool {
+    path.len() > 1 && path.ends_with('/')
+}
+
+fn toggle_trailing_slash(path: &str) -> String {
+    if has_trailing_slash(path) {
+        path.trim_end_matches('/').to_string()
+    } else {
+        format!("{}/", path)
+    }
+}
+
+// Panics on an unknown <name:type> (the route could never match) or a
+// catch-all that isn't last
+fn parse_segments(pattern: &str) -> Vec<Segment> {
+    let parts: Vec<&str> = pattern.trim_matches('/').split('/').collect();
+    let last = parts.len() - 1;
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let Some(param) = part.strip_prefix('<').and_then(|s| s.strip_suffix('>')) else {
+                return Segment::Static(part.to_string());
+            };
+            if let Some(name) = param.strip_suffix("..") {
+                assert!(i == last, "catch-all <{}> must be the last segment of route {}", param, pattern);
+                return Segment::Rest(name.to_string());
+            }
+            let (name, kind) = param.split_once(':').unwrap_or((param, "str"));
+            assert!(
+                PARAM_TYPES.contains(&kind),
+                "unknown parameter type `{}` in route {}, expected one of {:?}",
+                kind,
+                pattern,
+                PARAM_TYPES
+            );
+            Segment::Param {
+                name: name.to_string(),
+                kind: kind.to_string(),
+            }
+        })
+        .collect()
+}
+
+// Build the constraint list for route_with:
+// constraints!{"date" => r"\d{4}-\d{2}-\d{2}", "id" => r"[0-9]+"}
+#[macro_export]
+macro_rules! constraints {
+    ($($name:expr => $pattern:expr),* $(,)?) => {
+        &[$(($name, $pattern)),*]
+    };
+}
+
+// Static segments beat parameters that are typed or constrained, which beat
+// plain <name> parameters, which beat a catch-all. So `/users/me` wins over
+// `/users/<id>` whichever was registered first.
+fn segment_rank(segment: &Segment, constraints: &[(String, Regex)]) -> u8 {
+    match segment {
+        Segment::Static(_) => 3,
+        Segment::Param { name, kind } => {
+            if kind != "str" || constraints.iter().any(|(constrained, _)| constrained == name) {
+                2
+            } else {
+                1
+            }
+        }
+        Segment::Rest(_) => 0,
+    }
+}
+
+pub(crate) fn matches_type(kind: &str, value: &str) -> bool {
+    match kind {
+        "int" => value.parse::<i64>().is_ok(),
+        "uint" => value.parse::<u64>().is_ok(),
+        "float" => value.parse::<f64>().is_ok_and(f64::is_finite),
+        "uuid" => is_uuid(value),
+        _ => !value.is_empty(),
+    }
+}
+
+// 8-4-4-4-12 hex digits
+fn is_uuid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERNS: &[&str] = &[
+        "/users/<id>",
+        "/users/me",
+        "/items/<name>",
+        "/items/<id:int>",
+        "/files/<rest..>",
+        "/files/readme",
+        "/files/<name>/raw",
+    ];
+
+    fn router(patterns: &[&str]) -> Router {
+        let mut router = Router::new();
+        for pattern in patterns {
+            router.add_route("GET", pattern, |_, _| "");
+        }
+        router
+    }
+
+    // The pattern of the route that answers, with its parameters
+    fn matched(router: &Router, path: &str) -> Option<(String, Vec<(String, String)>)> {
+        match router.lookup("GET", path) {
+            RouteMatch::Found(route, params) => {
+                let mut params: Vec<_> = params.0.into_iter().collect();
+                params.sort();
+                Some((route.path.clone(), params))
+            }
+            _ => None,
+        }
+    }
+
+    fn pattern(router: &Router, path: &str) -> Option<String> {
+        matched(router, path).map(|(pattern, _)| pattern)
+    }
+
+    #[test]
+    fn static_segment_beats_parameter() {
+        let router = router(PATTERNS);
+        assert_eq!(pattern(&router, "/users/me").as_deref(), Some("/users/me"));
+        assert_eq!(
+            matched(&router, "/users/42"),
+            Some(("/users/<id>".to_string(), vec![("id".to_string(), "42".to_string())]))
+        );
+    }
+
+    #[test]
+    fn typed_parameter_beats_str() {
+        let router = router(PATTERNS);
+        assert_eq!(pattern(&router, "/items/5").as_deref(), Some("/items/<id:int>"));
+        assert_eq!(pattern(&router, "/items/-5").as_deref(), Some("/items/<id:int>"));
+        assert_eq!(pattern(&router, "/items/five").as_deref(), Some("/items/<name>"));
+    }
+
+    #[test]
+    fn fixed_routes_beat_catch_all() {
+        let router = router(PATTERNS);
+        assert_eq!(pattern(&router, "/files/readme").as_deref(), Some("/files/readme"));
+        assert_eq!(pattern(&router, "/files/a/raw").as_deref(), Some("/files/<name>/raw"));
+        assert_eq!(
+            matched(&router, "/files/a/b/c"),
+            Some(("/files/<rest..>".to_string(), vec![("rest".to_string(), "a/b/c".to_string())]))
+        );
+    }
+
+    fn head_answered_by(router: &Router, path: &str) -> Option<String> {
+        match router.lookup("HEAD", path) {
+            RouteMatch::Found(route, _) => Some(route.method.clone()),
+            _ => None,
         }
     }
-    Some(params)
-}
\ No newline at end of file
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let mut router = router(&["/a", "/b"]);
+        assert_eq!(head_answered_by(&router, "/a").as_deref(), Some("GET"));
+        // Registered after the GET route, and merged in from another router
+        router.add_route("HEAD", "/a", |_, _| "");
+        let mut other = Router::new();
+        other.add_route("HEAD", "/b", |_, _| "");
+        router.merge(other);
+        assert_eq!(head_answered_by(&router, "/a").as_deref(), Some("HEAD"));
+        assert_eq!(head_answered_by(&router, "/b").as_deref(), Some("HEAD"));
+        assert_eq!(head_answered_by(&router, "/c"), None);
+    }
+
+    #[test]
+    fn registration_order_does_not_matter() {
+        let forward = router(PATTERNS);
+        let reversed: Vec<&str> = PATTERNS.iter().rev().copied().collect();
+        let reversed = router(&reversed);
+        for path in [
+            "/users/me",
+            "/users/42",
+            "/items/5",
+            "/items/five",
+            "/files/readme",
+            "/files/a/raw",
+            "/files/a/b/c",
+            "/nowhere",
+        ] {
+            assert_eq!(matched(&forward, path), matched(&reversed, path), "{}", path);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// Generators for /robots.txt and /sitemap.xml
+
+pub type SitemapProvider = fn() -> Vec<String>;
+
+#[derive(Clone)]
+struct RobotsGroup {
+    user_agent: String,
+    rules: Vec<(bool, String)>,
+}
+
+#[derive(Clone)]
+pub struct Robots {
+    groups: Vec<RobotsGroup>,
+    sitemaps: Vec<String>,
+}
+
+impl Default for Robots {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Robots {
+    // Starts with a `User-agent: *` group
+    pub fn new() -> Self {
+        Self {
+            groups: vec![RobotsGroup {
+                user_agent: "*".to_string(),
+                rules: Vec::new(),
+            }],
+            sitemaps: Vec::new(),
+        }
+    }
+
+    // Start a new group; following allow/disallow calls apply to it
+    pub fn user_agent(mut self, agent: &str) -> Self {
+        if let Some(last) = self.groups.last_mut()
+            && last.rules.is_empty()
+            && last.user_agent == "*"
+        {
+            last.user_agent = agent.to_string();
+            return self;
+        }
+        self.groups.push(RobotsGroup {
+            user_agent: agent.to_string(),
+            rules: Vec::new(),
+        });
+        self
+    }
+
+    pub fn allow(self, path: &str) -> Self {
+        self.rule(true, path)
+    }
+
+    pub fn disallow(self, path: &str) -> Self {
+        self.rule(false, path)
+    }
+
+    pub fn sitemap(mut self, url: &str) -> Self {
+        self.sitemaps.push(url.to_string());
+        self
+    }
+
+    fn rule(mut self, allow: bool, path: &str) -> Self {
+        if let Some(group) = self.groups.last_mut() {
+            group.rules.push((allow, path.to_string()));
+        }
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for group in &self.groups {
+            out.push_str(&format!("User-agent: {}\n", group.user_agent));
+            if group.rules.is_empty() {
+                // An empty Disallow means "everything is allowed"
+                out.push_str("Disallow:\n");
+            }
+            for (allow, path) in &group.rules {
+                let directive = if *allow { "Allow" } else { "Disallow" };
+                out.push_str(&format!("{}: {}\n", directive, path));
+            }
+            out.push('\n');
+        }
+        for url in &self.sitemaps {
+            out.push_str(&format!("Sitemap: {}\n", url));
+        }
+        out
+    }
+}
+
+#[derive(Clone)]
+pub struct Sitemap {
+    base_url: String,
+    providers: Vec<SitemapProvider>,
+}
+
+impl Sitemap {
+    // Relative paths are resolved against base_url, e.g. "https://example.com"
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            providers: Vec::new(),
+        }
+    }
+
+    // Add a callback producing dynamic URLs (e.g. one per blog post)
+    pub fn provider(mut self, provider: SitemapProvider) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    pub fn render(&self, route_paths: &[String]) -> String {
+        let mut urls: Vec<String> = route_paths.to_vec();
+        for provider in &self.providers {
+            urls.extend(provider());
+        }
+
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+        let mut seen = std::collections::HashSet::new();
+        for url in urls {
+            let loc = if url.starts_with("http://") || url.starts_with("https://") {
+                url
+            } else {
+                format!("{}/{}", self.base_url, url.trim_start_matches('/'))
+            };
+            if seen.insert(loc.clone()) {
+                out.push_str(&format!("  <url><loc>{}</loc></url>\n", xml_escape(&loc)));
+            }
+        }
+        out.push_str("</urlset>\n");
+        out
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -1,32 +1,104 @@
-use crate::request::Request;
-use crate::response::Response;
-use crate::router::{Router, HandlerFn};
+use crate::archive::Archive;
+use crate::assets::{self, Assets};
+use crate::base64;
+use crate::body::{BodyReader, Framing};
+use crate::chunked::{self, ChunkError};
+use crate::compress::compress_response;
+use crate::concurrency::{apply_not_modified, precondition_required};
+use crate::cookie::Cookie;
+use crate::cors::Cors;
+use crate::docs::{self, RouteDoc};
+use crate::error::Error;
+use crate::extensions::Extensions;
+use crate::forwarded::{client_ip, TrustedProxy};
+use crate::h2c::{self, H2Request, Upgrade};
+use crate::http_date::http_date;
+use crate::idempotency::IdempotencyStore;
+use crate::metrics::{Metrics, WorkerHandle};
+use crate::proxy::Proxy;
+use crate::request::{fold_headers, parse_urlencoded, raw_parts, split_request_target, Request};
+use crate::response::{IntoResponse, Response, SECURITY_HEADERS};
+use crate::router::{
+    Params, PathCase, RouteGroup, RouteHandle, RouteInfo, RouteMatch, Router, TrailingSlash,
+};
+use crate::seo::{Robots, Sitemap};
+use crate::single_flight::SingleFlight;
+use crate::socket::{bind_listener, AcceptHook, SocketOptions};
+use crate::static_files;
 use crate::template::TemplateEngine;
+use crate::urls::{self, NamedRoutes};
+use crate::well_known::{AcmeChallenges, WellKnown};
+use crate::writable::WritableMount;
 
 use std::collections::HashMap;
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::io::{self, Read, Write};
+use std::process::Command;
 use std::thread;
 use std::path::Path;
-use std::fs;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub type BeforeMiddleware = fn(&mut Request) -> Option<Response>;
-pub type AfterMiddleware = fn(&Request, &mut Response);
-pub type ErrorHandlerFn = fn(&Request, u16) -> Response;
+pub type BeforeMiddleware = Arc<dyn Fn(&mut Request) -> Option<Response> + Send + Sync>;
+pub type AfterMiddleware = Arc<dyn Fn(&Request, &mut Response) + Send + Sync>;
+pub type ErrorHandlerFn = Arc<dyn Fn(&Request, u16) -> Response + Send + Sync>;
+pub type FallbackFn = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+pub type ErrorRendererFn = Arc<dyn Fn(&Request, &Error) -> Response + Send + Sync>;
 
 static SESSION_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static REQUEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+// Longest incoming X-Request-Id that is passed on rather than replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(Clone)]
 pub struct SimpleHttpServer {
     router: Router,
     error_handlers: HashMap<u16, ErrorHandlerFn>,
+    error_renderer: Option<ErrorRendererFn>,
+    fallback: Option<FallbackFn>,
     pub static_dir: Option<String>,
     sessions: Arc<Mutex<HashMap<String, HashMap<String, String>>>>,
     template_engine: Option<Arc<dyn TemplateEngine>>,
     before_middlewares: Vec<BeforeMiddleware>,
     after_middlewares: Vec<AfterMiddleware>,
+    proxy: Option<Proxy>,
+    cors: Option<Cors>,
+    robots: Option<Robots>,
+    sitemap: Option<Sitemap>,
+    well_known: WellKnown,
+    require_content_length: bool,
+    max_body_size: Option<usize>,
+    conditional_get: bool,
+    idempotency: Option<IdempotencyStore>,
+    single_flight: SingleFlight,
+    precompress: bool,
+    compress_responses: bool,
+    metrics: Metrics,
+    socket_options: SocketOptions,
+    on_accept: Option<AcceptHook>,
+    ipv6_only: Option<bool>,
+    trusted_proxies: Vec<TrustedProxy>,
+    server_header: Option<String>,
+    date_header: bool,
+    request_timeout: Option<Duration>,
+    request_deadline: Option<Duration>,
+    h2c: bool,
+    default_headers: Vec<(String, String)>,
+    archives: Vec<(String, Arc<Archive>)>,
+    writable_mounts: Vec<(String, WritableMount)>,
+    route_docs: bool,
+    route_descriptions: HashMap<(String, String), String>,
+    fingerprint_assets: bool,
+    assets: Assets,
+    vhosts: Vec<(String, Router)>,
+}
+
+impl Default for SimpleHttpServer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SimpleHttpServer {
@@ -34,65 +106,556 @@ impl SimpleHttpServer {
         Self {
             router: Router::new(),
             error_handlers: HashMap::new(),
+            error_renderer: None,
+            fallback: None,
             static_dir: None,
             sessions: Arc::new(Mutex::new(HashMap::new())),
             template_engine: None,
             before_middlewares: Vec::new(),
             after_middlewares: Vec::new(),
+            proxy: None,
+            cors: None,
+            robots: None,
+            sitemap: None,
+            well_known: WellKnown::default(),
+            require_content_length: false,
+            max_body_size: Some(DEFAULT_MAX_BODY_SIZE),
+            conditional_get: true,
+            idempotency: None,
+            single_flight: SingleFlight::default(),
+            precompress: false,
+            compress_responses: false,
+            metrics: Metrics::default(),
+            socket_options: SocketOptions::default(),
+            on_accept: None,
+            ipv6_only: None,
+            trusted_proxies: Vec::new(),
+            server_header: Some(concat!("rake/", env!("CARGO_PKG_VERSION")).to_string()),
+            date_header: true,
+            request_timeout: Some(Duration::from_secs(30)),
+            request_deadline: None,
+            h2c: false,
+            default_headers: Vec::new(),
+            archives: Vec::new(),
+            writable_mounts: Vec::new(),
+            route_docs: false,
+            route_descriptions: HashMap::new(),
+            fingerprint_assets: false,
+            assets: Assets::default(),
+            vhosts: Vec::new(),
         }
     }
 
-    pub fn route(&mut self, method: &str, path: &str, handler: HandlerFn) {
-        self.router.add_route(method, path, handler);
+    pub fn route<H, R>(&mut self, method: &str, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_route(method, path, handler)
+    }
+
+    // Shortcuts for route() with the method spelled out, so a typo in the
+    // method is a compile error rather than a route that never matches
+    pub fn get<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("POST", path, handler)
+    }
+
+    pub fn put<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("PUT", path, handler)
+    }
+
+    pub fn delete<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("DELETE", path, handler)
+    }
+
+    pub fn patch<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route("PATCH", path, handler)
+    }
+
+    // Match every HTTP method for `path`, e.g. webhooks where the sender
+    // picks the verb
+    pub fn any<H, R>(&mut self, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.any(path, handler)
+    }
+
+    // A route that only matches requests for API `version` (see
+    // Request::api_version), so one path can have a handler per version.
+    // Requests naming no version fall through to an unversioned route for
+    // the same path, if there is one.
+    pub fn route_versioned<H, R>(&mut self, method: &str, path: &str, version: u32, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(method, path, handler)
+            .guard(move |req| req.api_version() == Some(version))
+    }
+
+    // Send every method on `from` to `to` with a 3xx status, e.g.
+    // redirect("/old-path", "/new-path", 301). `<name>` in `to` is replaced
+    // by that parameter of `from`, and the query string is kept.
+    pub fn redirect(&mut self, from: &str, to: &str, code: u16) -> RouteHandle<'_> {
+        assert!((300..400).contains(&code), "redirect {} -> {}: {} is not a 3xx status", from, to, code);
+        let to = to.to_string();
+        self.router.any(from, move |req, params| {
+            let mut location = to.clone();
+            // Values arrive decoded, so they are encoded again: a raw CR or
+            // LF would otherwise end the Location header early
+            for (name, value) in params.iter() {
+                location = location.replace(&format!("<{}>", name), &urls::encode_path(value));
+            }
+            if let Some((_, query)) = req.target().split_once('?') {
+                location = format!("{}?{}", location, query);
+            }
+            Response::new(code, Vec::new(), "text/plain").with_header("Location", &location)
+        })
+    }
+
+    // A route that can be linked to by name with url_for, e.g.
+    // route_named("user_detail", "GET", "/users/<id>", user_detail).
+    // Panics if the name is already used for a different pattern.
+    pub fn route_named<H, R>(&mut self, name: &str, method: &str, path: &str, handler: H) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_named_route(name, method, path, handler)
+    }
+
+    // Route whose parameters must also match the given regexes, e.g.
+    // route_with("GET", "/report/<date>", constraints!{"date" => r"\d{4}-\d{2}-\d{2}"}, report)
+    pub fn route_with<H, R>(
+        &mut self,
+        method: &str,
+        path: &str,
+        constraints: &[(&str, &str)],
+        handler: H,
+    ) -> RouteHandle<'_>
+    where
+        H: Fn(&Request, &Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_route_with(method, path, constraints, handler)
+    }
+
+    // Register routes under a shared prefix, e.g.
+    // let mut api = server.group("/api/v1"); api.route("GET", "/users", list_users);
+    pub fn group(&mut self, prefix: &str) -> RouteGroup<'_> {
+        self.router.group(prefix)
+    }
+
+    // Route requests for `host` (the Host header, port ignored) with their own
+    // router, e.g. vhost("api.example.com", api_router). "*.example.com"
+    // matches any subdomain. Other hosts use the routes on the server itself.
+    pub fn vhost(&mut self, host: &str, router: Router) {
+        self.vhosts.push((host.to_ascii_lowercase(), router));
+    }
+
+    // Add the routes of `router`, e.g. from a plugin, to the server's own.
+    // Panics on a route that conflicts with one already registered.
+    pub fn merge(&mut self, router: Router) {
+        self.router.merge(router);
+    }
+
+    // The server's own routes (not those of vhosts) in registration order
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.router.routes()
+    }
+
+    // URL of one of the server's own named routes; handlers use
+    // req.url_for(), which also knows those of vhosts
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        self.router.url_for(name, params)
+    }
+
+    // Whether "/foo" and "/foo/" are the same route (the default), distinct,
+    // or redirected to the form the route was registered with
+    pub fn trailing_slash(&mut self, policy: TrailingSlash) {
+        self.router.set_trailing_slash(policy);
+    }
+
+    // Whether "/About" matches a route registered as "/about": no (the
+    // default), yes, or via a redirect to the registered spelling
+    pub fn path_case(&mut self, policy: PathCase) {
+        self.router.set_path_case(policy);
+    }
+
+    // Percent-decode path segments before matching and capturing params
+    // (the default); req.path always keeps the raw form
+    pub fn decode_path(&mut self, decode: bool) {
+        self.router.set_decode_path(decode);
     }
 
     pub fn static_dir(&mut self, dir: &str) {
         self.static_dir = Some(dir.to_string());
     }
 
-    pub fn error_handler(&mut self, code: u16, handler: ErrorHandlerFn) {
-        self.error_handlers.insert(code, handler);
+    // Serve the contents of a .zip, .tar or .tar.gz archive under `prefix`,
+    // e.g. static_archive("/assets", "assets.zip"). The archive is indexed now.
+    pub fn static_archive(&mut self, prefix: &str, archive_path: &str) -> io::Result<()> {
+        let archive = Archive::open(Path::new(archive_path))?;
+        self.archives.push((prefix.to_string(), Arc::new(archive)));
+        Ok(())
+    }
+
+    // Accept authorized PUT/DELETE of files under `prefix`, e.g.
+    // writable_mount("/uploads", WritableMount::new("uploads").authorize(check_token))
+    pub fn writable_mount(&mut self, prefix: &str, mount: WritableMount) {
+        self.writable_mounts.push((prefix.to_string(), mount));
+    }
+
+    pub fn error_handler<H>(&mut self, code: u16, handler: H)
+    where
+        H: Fn(&Request, u16) -> Response + Send + Sync + 'static,
+    {
+        self.error_handlers.insert(code, Arc::new(handler));
+    }
+
+    // Render the rake::Errors handlers return, e.g. as JSON for API clients
+    // and a page for browsers:
+    //
+    //     server.error_renderer(|req, err| match req.accepts("text/html") {
+    //         true => Response::html(&error_page(err.status(), err.message())),
+    //         false => Response::json(&json!({"error": err.message()})),
+    //     });
+    //
+    // The status is set to the error's afterwards. Without one they are
+    // sent as text/plain.
+    pub fn error_renderer<H>(&mut self, renderer: H)
+    where
+        H: Fn(&Request, &Error) -> Response + Send + Sync + 'static,
+    {
+        self.error_renderer = Some(Arc::new(renderer));
+    }
+
+    // Answer requests that match no route or static file, instead of the
+    // 404 error handler, e.g. to serve a SPA's index.html. A configured
+    // proxy takes precedence.
+    pub fn fallback<H>(&mut self, handler: H)
+    where
+        H: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.fallback = Some(Arc::new(handler));
     }
 
     pub fn set_template_engine(&mut self, engine: Arc<dyn TemplateEngine>) {
         self.template_engine = Some(engine);
     }
 
-    pub fn add_before_middleware(&mut self, mw: BeforeMiddleware) {
-        self.before_middlewares.push(mw);
+    pub fn add_before_middleware<M>(&mut self, mw: M)
+    where
+        M: Fn(&mut Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        self.before_middlewares.push(Arc::new(mw));
+    }
+
+    pub fn add_after_middleware<M>(&mut self, mw: M)
+    where
+        M: Fn(&Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.after_middlewares.push(Arc::new(mw));
+    }
+
+    // Answer CORS preflights and add Access-Control-Allow-* headers for
+    // the origins `cors` allows
+    pub fn cors(&mut self, cors: Cors) {
+        self.cors = Some(cors);
+    }
+
+    // Forward requests that match no route or static file to an upstream
+    pub fn proxy(&mut self, proxy: Proxy) {
+        self.proxy = Some(proxy);
+    }
+
+    // Serve an HTML table of all routes at /_rake/routes. Meant for
+    // development and internal APIs; off by default.
+    pub fn route_docs(&mut self, enabled: bool) {
+        self.route_docs = enabled;
+    }
+
+    // Text shown next to a route on the /_rake/routes page
+    pub fn describe(&mut self, method: &str, path: &str, description: &str) {
+        self.route_descriptions.insert(
+            (method.to_uppercase(), path.to_string()),
+            description.to_string(),
+        );
+    }
+
+    // Serve a generated /robots.txt
+    pub fn robots(&mut self, robots: Robots) {
+        self.robots = Some(robots);
+    }
+
+    // Serve a generated /sitemap.xml listing parameterless GET routes plus provider URLs
+    pub fn sitemap(&mut self, sitemap: Sitemap) {
+        self.sitemap = Some(sitemap);
+    }
+
+    // Register a document served at /.well-known/<name>, e.g. "security.txt"
+    pub fn well_known(&mut self, name: &str, body: &[u8], content_type: &str) {
+        self.well_known.insert(name, body.to_vec(), content_type);
+    }
+
+    // Handle for setting ACME HTTP-01 tokens at runtime; answers
+    // GET /.well-known/acme-challenge/<token>
+    pub fn acme_challenges(&self) -> AcmeChallenges {
+        self.well_known.acme.clone()
+    }
+
+    // Answer 411 Length Required to POST/PUT/PATCH requests without a Content-Length
+    pub fn require_content_length(&mut self, required: bool) {
+        self.require_content_length = required;
+    }
+
+    // Largest request body accepted (10 MiB by default); None for no limit.
+    // A larger Content-Length gets 413 before any of the body is read, and a
+    // chunked body gets 413 once it grows past the limit (counting chunk
+    // framing). For .stream_body() routes the reader returns an error instead.
+    pub fn max_body_size(&mut self, limit: Option<usize>) {
+        self.max_body_size = limit;
     }
 
-    pub fn add_after_middleware(&mut self, mw: AfterMiddleware) {
-        self.after_middlewares.push(mw);
+    // Answer a GET/HEAD with 304 Not Modified when the handler's 200 response
+    // carries an ETag or Last-Modified that the request's If-None-Match or
+    // If-Modified-Since shows the client already has. On by default; runs
+    // after the after-middlewares, so headers they add count too.
+    pub fn conditional_get(&mut self, enabled: bool) {
+        self.conditional_get = enabled;
     }
 
+    // Remember responses to unsafe requests carrying an Idempotency-Key header
+    // and replay them when the client retries within `ttl`
+    pub fn idempotency_keys(&mut self, ttl: Duration) {
+        self.idempotency = Some(IdempotencyStore::new(ttl));
+    }
+
+    // On start, hash the static dir so every file is also served under a
+    // content-fingerprinted name with an immutable Cache-Control. Handlers
+    // link to them with req.asset("app.css") or req.expand_assets().
+    pub fn fingerprint_assets(&mut self, enabled: bool) {
+        self.fingerprint_assets = enabled;
+    }
+
+    // On start, write .gz (and with the brotli feature .br) siblings for
+    // compressible files in the static dir (skipping up-to-date ones) so they
+    // are served without runtime compression. Clients that accept br get it
+    // in preference to gzip.
+    pub fn precompress_static(&mut self, enabled: bool) {
+        self.precompress = enabled;
+    }
+
+    // Compress text responses on the fly for clients that accept it: br
+    // with the brotli feature, otherwise gzip. Static files with an
+    // up-to-date precompressed sibling are served as they are.
+    pub fn compress_responses(&mut self, enabled: bool) {
+        self.compress_responses = enabled;
+    }
+
+    // Handle to live statistics: requests started and time spent on them by
+    // each `rake-worker-N` thread serving a connection (requests still
+    // running included), and the totals so far
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    // TCP tuning (nodelay, keepalive, linger, buffer sizes) for accepted sockets
+    pub fn socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    // Called with every accepted socket before its thread starts, for
+    // tuning that SocketOptions doesn't cover
+    pub fn on_accept<H>(&mut self, hook: H)
+    where
+        H: Fn(&TcpStream) + Send + Sync + 'static,
+    {
+        self.on_accept = Some(Arc::new(hook));
+    }
+
+    // For IPv6 addresses like "[::]:8080": true accepts only IPv6 clients,
+    // false also accepts IPv4 (dual-stack). Unset keeps the OS default.
+    pub fn ipv6_only(&mut self, only: bool) {
+        self.ipv6_only = Some(only);
+    }
+
+    // Addresses or networks ("10.0.0.1", "10.0.0.0/8", "fd00::/8") of reverse
+    // proxies whose Forwarded/X-Forwarded-For headers req.client_ip() may
+    // believe. Panics on an invalid entry.
+    pub fn trusted_proxies(&mut self, proxies: &[&str]) {
+        self.trusted_proxies = proxies
+            .iter()
+            .map(|proxy| {
+                TrustedProxy::parse(proxy).unwrap_or_else(|| panic!("invalid trusted proxy address: {}", proxy))
+            })
+            .collect();
+    }
+
+    // Header added to every response that doesn't already set it
+    pub fn default_header(&mut self, key: &str, value: &str) {
+        self.default_headers
+            .retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.default_headers.push((key.to_string(), value.to_string()));
+    }
+
+    // X-Content-Type-Options, X-Frame-Options and Referrer-Policy on every
+    // response, as default headers, so a handler can still set its own
+    pub fn security_headers(&mut self) {
+        for (key, value) in SECURITY_HEADERS {
+            self.default_header(key, value);
+        }
+    }
+
+    // Strict-Transport-Security on every response, telling browsers to use
+    // only HTTPS for `max_age`. The server itself speaks plain HTTP, so this
+    // is for running behind a proxy that terminates TLS; browsers ignore it
+    // over HTTP.
+    pub fn hsts(&mut self, max_age: Duration) {
+        self.default_header("Strict-Transport-Security", &format!("max-age={}", max_age.as_secs()));
+    }
+
+    // Value of the Server response header; None leaves it out
+    pub fn server_header(&mut self, value: Option<&str>) {
+        self.server_header = value.map(|v| v.to_string());
+    }
+
+    // Whether responses get an automatic Date header (on by default)
+    pub fn date_header(&mut self, enabled: bool) {
+        self.date_header = enabled;
+    }
+
+    // How long a client may take to send its request head, or to send more
+    // of its body, before getting 408 Request Timeout (30s by default);
+    // None waits forever
+    pub fn request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    // Time budget for a whole request, counted from when the connection is
+    // picked up, that handlers see as req.deadline() and can hand on to
+    // database or upstream calls. Handlers are not interrupted when it
+    // passes. None (the default) gives requests no deadline.
+    pub fn request_deadline(&mut self, budget: Option<Duration>) {
+        self.request_deadline = budget;
+    }
+
+    // Also speak HTTP/2 over plain TCP (h2c, off by default): to clients
+    // that open with the HTTP/2 preface, and to HTTP/1.1 requests asking
+    // for it with `Upgrade: h2c`. Each stream is answered on its own thread,
+    // so requests on one connection don't wait on each other.
+    pub fn h2c(&mut self, enabled: bool) {
+        self.h2c = enabled;
+    }
+
+    // Bind and serve forever; exits the process with a clear message if the
+    // address can't be bound
     pub fn start(&self, addr: &str) {
-        let listener = TcpListener::bind(addr).expect("Failed to bind to address");
-        println!("Listening on {}", addr);
+        if let Err(e) = self.try_start(addr) {
+            eprintln!("Failed to start server on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    }
+
+    // Like `start`, but hands bind errors back to the caller
+    pub fn try_start(&self, addr: &str) -> io::Result<()> {
+        let listener = bind_listener(addr, self.ipv6_only)?;
+        self.serve(listener);
+        Ok(())
+    }
+
+    // Development mode: if the port is taken, move on to the next free one,
+    // print a clickable URL and optionally open it in the default browser
+    pub fn dev_start(&self, addr: &str, open_browser: bool) {
+        let listener = match dev_bind(addr, self.ipv6_only) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Failed to start dev server on {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        };
+        if let Ok(local) = listener.local_addr() {
+            let url = browser_url(local);
+            println!("\n  Rake dev server running at {}\n", url);
+            if open_browser {
+                open_in_browser(&url);
+            }
+        }
+        self.serve(listener);
+    }
+
+    fn serve(&self, listener: TcpListener) {
+        if self.precompress
+            && let Some(dir) = &self.static_dir
+        {
+            match static_files::precompress_dir(Path::new(dir)) {
+                Ok(count) => println!("Precompressed {} static file(s) in {}", count, dir),
+                Err(e) => eprintln!("Precompressing {} failed: {}", dir, e),
+            }
+        }
+        if self.fingerprint_assets
+            && let Some(dir) = &self.static_dir
+        {
+            match self.assets.fingerprint_dir(Path::new(dir)) {
+                Ok(count) => println!("Fingerprinted {} static file(s) in {}", count, dir),
+                Err(e) => eprintln!("Fingerprinting {} failed: {}", dir, e),
+            }
+        }
+
+        if let Ok(local) = listener.local_addr() {
+            println!("Listening on {}", local);
+        }
+
+        // Shared state lives behind Arcs, so one snapshot serves every connection
+        let server = Arc::new(self.clone());
+        let mut next_worker = 0u64;
 
         for stream in listener.incoming() {
             match stream {
                 Ok(stream) => {
-                    let router = self.router.clone();
-                    let error_handlers = self.error_handlers.clone();
-                    let static_dir = self.static_dir.clone();
-                    let sessions = Arc::clone(&self.sessions);
-                    let template_engine = self.template_engine.clone();
-                    let before_middlewares = self.before_middlewares.clone();
-                    let after_middlewares = self.after_middlewares.clone();
-
-                    thread::spawn(move || {
-                        handle_connection(
-                            stream,
-                            router,
-                            error_handlers,
-                            static_dir,
-                            sessions,
-                            template_engine,
-                            before_middlewares,
-                            after_middlewares,
-                        );
+                    if let Err(e) = self.socket_options.apply(&stream) {
+                        eprintln!("Failed to apply socket options: {}", e);
+                    }
+                    if let Some(hook) = &self.on_accept {
+                        hook(&stream);
+                    }
+                    let server = Arc::clone(&server);
+                    let metrics = self.metrics.clone();
+                    next_worker += 1;
+                    let name = format!("rake-worker-{}", next_worker);
+                    let spawned = thread::Builder::new().name(name.clone()).spawn(move || {
+                        let worker = metrics.register_worker(&name);
+                        handle_connection(stream, &server, &worker);
                     });
+                    if let Err(e) = spawned {
+                        eprintln!("Failed to spawn a connection thread: {}", e);
+                    }
                 }
                 Err(e) => eprintln!("Connection failed: {}", e),
             }
@@ -100,6 +663,48 @@ impl SimpleHttpServer {
     }
 }
 
+// Try the requested port, then the next few, then let the OS pick one
+fn dev_bind(addr: &str, ipv6_only: Option<bool>) -> io::Result<TcpListener> {
+    let err = match bind_listener(addr, ipv6_only) {
+        Ok(listener) => return Ok(listener),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => e,
+        Err(e) => return Err(e),
+    };
+    let Some((host, port)) = addr.rsplit_once(':') else {
+        return Err(err);
+    };
+    let port: u16 = port.parse().map_err(|_| err)?;
+    for candidate in (port.saturating_add(1)..=port.saturating_add(20)).chain([0]) {
+        if let Ok(listener) = bind_listener(&format!("{}:{}", host, candidate), ipv6_only) {
+            println!("Port {} is in use, using another one instead", port);
+            return Ok(listener);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AddrInUse, format!("no free port near {}", port)))
+}
+
+// Wildcard addresses aren't browsable; point at the loopback instead
+fn browser_url(addr: SocketAddr) -> String {
+    match addr.ip() {
+        ip if ip.is_unspecified() => format!("http://localhost:{}/", addr.port()),
+        IpAddr::V6(ip) => format!("http://[{}]:{}/", ip, addr.port()),
+        IpAddr::V4(ip) => format!("http://{}:{}/", ip, addr.port()),
+    }
+}
+
+fn open_in_browser(url: &str) {
+    let result = if cfg!(target_os = "macos") {
+        Command::new("open").arg(url).spawn()
+    } else if cfg!(target_os = "windows") {
+        Command::new("cmd").args(["/C", "start", "", url]).spawn()
+    } else {
+        Command::new("xdg-open").arg(url).spawn()
+    };
+    if let Err(e) = result {
+        eprintln!("Could not open a browser: {}", e);
+    }
+}
+
 fn generate_session_id() -> String {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -109,96 +714,542 @@ fn generate_session_id() -> String {
     format!("{:x}{:x}", now, count)
 }
 
-fn handle_connection(
-    mut stream: TcpStream,
-    router: Router,
-    error_handlers: HashMap<u16, ErrorHandlerFn>,
-    static_dir: Option<String>,
-    sessions: Arc<Mutex<HashMap<String, HashMap<String, String>>>>,
-    _template_engine: Option<Arc<dyn TemplateEngine>>,  // unused, prefixed with _
-    before_middlewares: Vec<BeforeMiddleware>,
-    after_middlewares: Vec<AfterMiddleware>,
-) {
-    let mut buffer = [0; 8192];
-    if let Ok(size) = stream.read(&mut buffer) {
-        let request_str = String::from_utf8_lossy(&buffer[..size]).to_string();
-        let (method, path, headers, body, query) = parse_http_request(&request_str);
-
-        let mut request = Request {
-            method: method.clone(),
-            path: path.clone(),
-            raw: request_str,
-            headers,
-            query,
-            body,
-        };
+// The client's X-Request-Id if it is short printable ASCII, so IDs from an
+// upstream service carry through; otherwise a new one
+fn request_id(headers: &mut Vec<(String, String)>) -> String {
+    if let Some((_, id)) = headers.iter().find(|(name, _)| name == "x-request-id")
+        && !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_graphic())
+    {
+        return id.clone();
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let count = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let id = format!("{:x}-{:x}", now, count);
+    headers.retain(|(name, _)| name != "x-request-id");
+    headers.push(("x-request-id".to_string(), id.clone()));
+    id
+}
 
-        // Run before middlewares
-        for mw in &before_middlewares {
-            if let Some(resp) = mw(&mut request) {
-                send_response(&mut stream, resp);
+fn handle_connection(mut stream: TcpStream, server: &SimpleHttpServer, worker: &WorkerHandle) {
+    let deadline = server.request_deadline.map(|budget| Instant::now() + budget);
+    let mut received = match read_head(&mut stream, server.request_timeout) {
+        Ok(received) => received,
+        Err(ReadError::TimedOut) => {
+            send_response(&mut stream, server, request_timeout(), None);
+            return;
+        }
+        Err(ReadError::TooLarge) => {
+            let response = Response::new(431, b"431 Request Header Fields Too Large".to_vec(), "text/plain");
+            send_response(&mut stream, server, response, None);
+            return;
+        }
+        Err(ReadError::Closed) => return,
+    };
+    if server.h2c && received.starts_with(b"PRI * HTTP/2.0\r\n") {
+        serve_h2c(stream, received, server, worker, None);
+        return;
+    }
+    let timer = worker.start_request();
+    let head_len = find_head_end(&received).unwrap_or(received.len());
+    let (method, path, version, mut header_lines, query) = parse_http_request(&received[..head_len]);
+    let request_id = request_id(&mut header_lines);
+    let headers = fold_headers(&header_lines);
+    let client_ip = client_ip(stream.peer_addr().ok(), &headers, &server.trusted_proxies);
+    let framing = body_framing(server, &method, &headers);
+    // HTTP/1.0 clients don't know 100 Continue, so Expect is ignored for them
+    // (RFC 9110 section 10.1.1)
+    let expect_continue = version != "HTTP/1.0"
+        && headers
+            .get("expect")
+            .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"));
+
+    // `raw` covers just the head until the body has been read
+    let (raw, raw_bytes) = raw_parts(received[..head_len].to_vec());
+    let mut request = Request {
+        method,
+        path,
+        version,
+        raw,
+        headers,
+        header_lines,
+        query,
+        body: Vec::new(),
+        trailers: HashMap::new(),
+        body_reader: Mutex::new(None),
+        path_params: Params::default(),
+        route_meta: Extensions::new(),
+        named_routes: NamedRoutes::default(),
+        assets: server.assets.clone(),
+        extensions: Extensions::new(),
+        local_addr: stream.local_addr().ok(),
+        remote_addr: stream.peer_addr().ok(),
+        client_ip,
+        raw_bytes,
+        request_id,
+        deadline,
+    };
+
+    let framing = match framing {
+        Ok(framing) => framing,
+        Err(code) => {
+            send_response(&mut stream, server, error_response(code, &request, &server.error_handlers), Some(&request));
+            return;
+        }
+    };
+    // Bytes after the message, for a connection switching to h2c
+    let mut rest = Vec::new();
+    if streams_body(server, &request) {
+        let Ok(body_stream) = stream.try_clone() else {
+            return;
+        };
+        let reader = BodyReader::new(
+            framing,
+            received.split_off(head_len),
+            body_stream,
+            server.request_timeout,
+            server.max_body_size,
+            expect_continue,
+        );
+        request.body_reader = Mutex::new(Some(reader));
+    } else {
+        // The first reads usually stop partway through a larger body
+        match read_body(&mut stream, server, framing, expect_continue, head_len, &mut received) {
+            Ok(()) => {}
+            Err(ReadError::TimedOut) => {
+                send_response(&mut stream, server, request_timeout(), Some(&request));
+                return;
+            }
+            Err(ReadError::TooLarge) => {
+                send_response(&mut stream, server, error_response(413, &request, &server.error_handlers), Some(&request));
                 return;
             }
+            Err(ReadError::Closed) => return,
         }
+        let body = match extract_body(framing, &received[head_len..]) {
+            Ok(body) => body,
+            Err(code) => {
+                send_response(&mut stream, server, error_response(code, &request, &server.error_handlers), Some(&request));
+                return;
+            }
+        };
+        // Anything after the message (a pipelined request) is dropped unless
+        // the connection switches to h2c, and the buffer itself becomes
+        // `raw` rather than being copied
+        rest = received.split_off((head_len + body.consumed).min(received.len()));
+        (request.raw, request.raw_bytes) = raw_parts(received);
+        request.body = body.data;
+        request.trailers = body.trailers;
+    }
 
-        // Session handling
-        let mut session_id = None;
-        if let Some(cookie_header) = request.headers.get("cookie") {
-            for cookie in cookie_header.split(';') {
-                let cookie = cookie.trim();
-                if let Some((k, v)) = cookie.split_once('=') {
-                    if k == "SESSIONID" {
-                        session_id = Some(v.to_string());
-                    }
-                }
+    if server.h2c
+        && let Some(settings) = h2c_upgrade(&request)
+    {
+        let _ = stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n");
+        let upgrade = Upgrade {
+            settings,
+            head_request: request.method == "HEAD",
+            respond: Box::new(move || {
+                let _timer = timer;
+                let mut response = respond(server, &mut request);
+                finish_response(server, &mut response, Some(&request));
+                response
+            }),
+        };
+        serve_h2c(stream, rest, server, worker, Some(upgrade));
+        return;
+    }
+
+    let response = respond(server, &mut request);
+    send_response(&mut stream, server, response, Some(&request));
+}
+
+// Everything from CORS preflights to the last touches on the handler's
+// response; the standard headers are left to the caller
+fn respond(server: &SimpleHttpServer, request: &mut Request) -> Response {
+    // Preflights carry no credentials, so they mustn't reach auth middleware
+    if let Some(response) = server.cors.as_ref().and_then(|cors| cors.preflight(request)) {
+        return response;
+    }
+
+    // Run before middlewares
+    for mw in &server.before_middlewares {
+        if let Some(resp) = mw(request) {
+            return resp;
+        }
+    }
+
+    // Session handling
+    let session_id = request.cookie("SESSIONID").unwrap_or_else(generate_session_id);
+
+    let mut sessions_lock = server.sessions.lock().unwrap();
+    let _session_data = sessions_lock.entry(session_id.clone()).or_default();
+    drop(sessions_lock); // release lock early
+
+    let response = match &server.idempotency {
+        Some(store) => store.run(request, |request| dispatch(server, request)),
+        None => dispatch(server, request),
+    };
+
+    let mut response = response.set_cookie(Cookie::new("SESSIONID", &session_id).http_only().path("/"));
+
+    // Logs 
+    println!(
+        "[{}] Request: {} => Status: {} (id {})",
+        request.method,
+        request.path,
+        response.status_code,
+        request.request_id
+    );
+
+    // Run after middlewares
+    for mw in &server.after_middlewares {
+        mw(request, &mut response);
+    }
+    if server.compress_responses {
+        response = compress_response(request, response);
+    }
+    if server.conditional_get {
+        response = apply_not_modified(request, response);
+    }
+    if response.header("x-request-id").is_none() {
+        response.headers.insert("X-Request-Id".to_string(), request.request_id.clone());
+    }
+    response
+}
+
+// The HTTP2-Settings of a request that asks to switch to h2c (RFC 7540
+// section 3.2), if it asks properly
+fn h2c_upgrade(request: &Request) -> Option<Vec<u8>> {
+    let lists = |name: &str, token: &str| {
+        request
+            .header(name)
+            .is_some_and(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    };
+    if request.version != "HTTP/1.1"
+        || !lists("upgrade", "h2c")
+        || !lists("connection", "upgrade")
+        || !lists("connection", "http2-settings")
+    {
+        return None;
+    }
+    base64::decode_url(request.header("http2-settings")?)
+}
+
+// Hands the connection over to HTTP/2. `preread` starts with the preface
+// (or whatever the client sent after the upgrade request).
+fn serve_h2c(
+    stream: TcpStream,
+    preread: Vec<u8>,
+    server: &SimpleHttpServer,
+    worker: &WorkerHandle,
+    upgrade: Option<Upgrade<'_>>,
+) {
+    let Ok(peer) = stream.try_clone() else {
+        return;
+    };
+    let respond = |request: H2Request| {
+        let _timer = worker.start_request();
+        respond_h2(&peer, server, request)
+    };
+    h2c::serve(stream, preread, server.request_timeout, server.max_body_size, upgrade, &respond);
+}
+
+// A request from an HTTP/2 stream, written out as an HTTP/1.1-style head
+// so it is parsed like any other
+fn respond_h2(peer: &TcpStream, server: &SimpleHttpServer, h2: H2Request) -> Response {
+    let deadline = server.request_deadline.map(|budget| Instant::now() + budget);
+    let mut head = format!("{} {} HTTP/2\r\n", h2.method, h2.target);
+    for (name, value) in &h2.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let (method, path, version, mut header_lines, query) = parse_http_request(head.as_bytes());
+    let request_id = request_id(&mut header_lines);
+    let headers = fold_headers(&header_lines);
+    let client_ip = client_ip(peer.peer_addr().ok(), &headers, &server.trusted_proxies);
+    let mut message = head.into_bytes();
+    message.extend_from_slice(&h2.body);
+    let (raw, raw_bytes) = raw_parts(message);
+    let mut request = Request {
+        method,
+        path,
+        version,
+        raw,
+        headers,
+        header_lines,
+        query,
+        body: h2.body,
+        trailers: h2.trailers,
+        body_reader: Mutex::new(None),
+        path_params: Params::default(),
+        route_meta: Extensions::new(),
+        named_routes: NamedRoutes::default(),
+        assets: server.assets.clone(),
+        extensions: Extensions::new(),
+        local_addr: peer.local_addr().ok(),
+        remote_addr: peer.peer_addr().ok(),
+        client_ip,
+        raw_bytes,
+        request_id,
+        deadline,
+    };
+
+    let mut response = match h2.error {
+        Some(code) => error_response(code, &request, &server.error_handlers),
+        None => {
+            // The whole body is in already; streaming routes read it from memory
+            if streams_body(server, &request)
+                && let Ok(body_stream) = peer.try_clone()
+            {
+                let body = std::mem::take(&mut request.body);
+                let reader = BodyReader::new(
+                    Framing::Length(body.len()),
+                    body,
+                    body_stream,
+                    server.request_timeout,
+                    server.max_body_size,
+                    false,
+                );
+                request.body_reader = Mutex::new(Some(reader));
             }
+            respond(server, &mut request)
         }
-        let session_id = session_id.unwrap_or_else(generate_session_id);
-
-        let mut sessions_lock = sessions.lock().unwrap();
-        let _session_data = sessions_lock.entry(session_id.clone()).or_insert_with(HashMap::new);
-        drop(sessions_lock); // release lock early
-
-        // Match route
-        let response = if let Some((handler, params)) = router.find(&method, &path) {
-            handler(&request, &params)
-        } else if let Some(dir) = &static_dir {
-            // Serve static files
-            let full_path = Path::new(dir).join(path.trim_start_matches('/'));
-            match fs::read(&full_path) {
-                Ok(contents) => {
-                    let content_type = get_mime_type(&full_path);
-                    Response::new(200, contents, content_type)
+    };
+    finish_response(server, &mut response, Some(&request));
+    response
+}
+
+// Match route, then built-in documents, static files, the proxy and the
+// fallback handler. A path only routed for other methods gets 405 rather
+// than being proxied.
+fn dispatch(server: &SimpleHttpServer, request: &mut Request) -> Response {
+    let router = router_for(server, request);
+    request.named_routes = router.names.clone();
+    let allowed = match router.lookup_request(request) {
+        RouteMatch::Found(route, params) => {
+            request.path_params = params.clone();
+            request.route_meta = route.meta.clone();
+            let request = &*request;
+            for mw in &route.before {
+                if let Some(response) = mw(request) {
+                    return response;
                 }
-                Err(_) => error_response(404, &request, &error_handlers),
             }
-        } else {
-            error_response(404, &request, &error_handlers)
-        };
+            let mut response = if !route.consumes_request(request) {
+                error_response(415, request, &server.error_handlers)
+            } else if route.require_if_match && request.header("if-match").is_none() {
+                precondition_required()
+            } else if route.coalesce && request.method == "GET" {
+                server
+                    .single_flight
+                    .run(&coalescing_key(request), || (route.handler)(request, &params))
+            } else {
+                (route.handler)(request, &params)
+            };
+            if response.from_error
+                && let Some(render) = &server.error_renderer
+            {
+                let error = Error::from_response(&response);
+                response = render(request, &error);
+                response.status_code = error.status();
+            }
+            for mw in &route.after {
+                mw(request, &mut response);
+            }
+            return response;
+        }
+        RouteMatch::MethodNotAllowed(allowed) => Some(allowed),
+        RouteMatch::Redirect(location) => {
+            // 308 keeps the method and body, which 301 may not
+            let code = if matches!(request.method.as_str(), "GET" | "HEAD") { 301 } else { 308 };
+            let target = request.target();
+            let location = match target.split_once('?') {
+                Some((_, query)) => format!("{}?{}", location, query),
+                None => location,
+            };
+            return Response::new(code, Vec::new(), "text/plain").with_header("Location", &location);
+        }
+        RouteMatch::NotFound => None,
+    };
+
+    if let Some(response) = builtin_response(server, request) {
+        response
+    } else if let Some(response) = server
+        .writable_mounts
+        .iter()
+        .find_map(|(prefix, mount)| mount.handle(prefix, request))
+    {
+        response
+    } else if let Some(response) = server
+        .static_dir
+        .as_ref()
+        .and_then(|dir| serve_static(server, dir, request))
+    {
+        response
+    } else if let Some(response) = server
+        .archives
+        .iter()
+        .find_map(|(prefix, archive)| static_files::serve_archive(prefix, archive, request))
+    {
+        response
+    } else if let Some(allowed) = allowed {
+        error_response(405, request, &server.error_handlers).with_header("Allow", &allowed.join(", "))
+    } else if let Some(proxy) = &server.proxy {
+        match proxy.forward(request) {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Proxy error: {}", e);
+                error_response(502, request, &server.error_handlers)
+            }
+        }
+    } else if let Some(fallback) = &server.fallback {
+        fallback(request)
+    } else {
+        error_response(404, request, &server.error_handlers)
+    }
+}
 
-        let mut response = response.with_header("Set-Cookie", &format!("SESSIONID={}; HttpOnly; Path=/", session_id));
+// The vhost router for the request's Host, or the server's own
+fn router_for<'a>(server: &'a SimpleHttpServer, request: &Request) -> &'a Router {
+    let Some(host) = request.header("host") else {
+        return &server.router;
+    };
+    let host = host.trim().to_ascii_lowercase();
+    // Drop the port, minding IPv6 literals like "[::1]:8080"
+    let host = match host.rsplit_once(':') {
+        Some((name, port))
+            if !port.is_empty()
+                && port.bytes().all(|b| b.is_ascii_digit())
+                && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            name
+        }
+        _ => host.as_str(),
+    };
+    let host = host.trim_end_matches('.');
+    server
+        .vhosts
+        .iter()
+        .find(|(pattern, _)| match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => pattern == host,
+        })
+        .map(|(_, router)| router)
+        .unwrap_or(&server.router)
+}
 
-        // Logs 
-        println!(
-            "[{}] Request: {} => Status: {}",
-            method,
-            path,
-            response.status_code
-        );
+fn serve_static(server: &SimpleHttpServer, dir: &str, request: &Request) -> Option<Response> {
+    if server.fingerprint_assets
+        && let Some(original) = server.assets.original(&request.path)
+    {
+        let response = static_files::serve_file(&Path::new(dir).join(original), request)?;
+        return Some(response.with_header("Cache-Control", assets::IMMUTABLE));
+    }
+    static_files::serve(dir, request)
+}
 
-        // Run after middlewares
-        for mw in &after_middlewares {
-            mw(&request, &mut response);
+// Requests sharing a key get the same response, so it holds everything
+// the answer may depend on besides the route itself
+fn coalescing_key(request: &Request) -> String {
+    let mut key = request.target();
+    for name in ["host", "accept", "accept-encoding", "authorization", "cookie"] {
+        key.push('\n');
+        key.push_str(request.header(name).map_or("", |v| v.as_str()));
+    }
+    key
+}
+
+// Conventional documents the server generates itself; user routes take precedence
+fn builtin_response(server: &SimpleHttpServer, req: &Request) -> Option<Response> {
+    if !matches!(req.method.as_str(), "GET" | "HEAD") {
+        return None;
+    }
+    if let Some(name) = req.path.strip_prefix("/.well-known/") {
+        return server.well_known.respond(name);
+    }
+    match req.path.as_str() {
+        "/robots.txt" => {
+            let robots = server.robots.as_ref()?;
+            Some(Response::new(200, robots.render().into_bytes(), "text/plain"))
+        }
+        "/sitemap.xml" => {
+            let sitemap = server.sitemap.as_ref()?;
+            let body = sitemap.render(&router_for(server, req).static_get_paths());
+            Some(Response::new(200, body.into_bytes(), "application/xml"))
+        }
+        "/_rake/routes" if server.route_docs => {
+            let body = docs::render(&route_docs(server));
+            Some(Response::new(200, body.into_bytes(), "text/html"))
         }
+        _ => None,
+    }
+}
+
+fn route_docs(server: &SimpleHttpServer) -> Vec<RouteDoc<'_>> {
+    server
+        .routes()
+        .into_iter()
+        .map(|route| {
+            let key = (route.method.clone(), route.pattern.clone());
+            let mut notes = Vec::new();
+            if route.requires_if_match {
+                notes.push("Requires If-Match");
+            }
+            if route.coalesced {
+                notes.push("Concurrent requests coalesced");
+            }
+            RouteDoc {
+                description: server.route_descriptions.get(&key).map(|s| s.as_str()),
+                route,
+                notes,
+            }
+        })
+        .collect()
+}
+
+// HTTP/1.0 requests get an HTTP/1.0 status line. `request` is None when it
+// couldn't be read; the answer then goes out as HTTP/1.1.
+fn send_response(stream: &mut TcpStream, server: &SimpleHttpServer, mut response: Response, request: Option<&Request>) {
+    finish_response(server, &mut response, request);
+    let version = request.map_or("HTTP/1.1", |request| request.version.as_str());
+    let head_request = request.is_some_and(|request| request.method == "HEAD");
+    let _ = response.write_to(stream, version, head_request);
+}
 
-        send_response(&mut stream, response);
+// Standard headers and CORS, for every response however it's sent
+fn finish_response(server: &SimpleHttpServer, response: &mut Response, request: Option<&Request>) {
+    add_standard_headers(server, response);
+    if let (Some(cors), Some(request)) = (&server.cors, request) {
+        cors.decorate(request, response);
     }
 }
 
-fn send_response(stream: &mut TcpStream, response: Response) {
-    let http_response = response.to_http();
-    let _ = stream.write_all(&http_response);
-    let _ = stream.flush();
+// Defaults, Date, Server and Connection, unless the handler set them itself
+fn add_standard_headers(server: &SimpleHttpServer, response: &mut Response) {
+    for (key, value) in &server.default_headers {
+        if response.header(key).is_none() {
+            response.headers.insert(key.clone(), value.clone());
+        }
+    }
+    if server.date_header && response.header("date").is_none() {
+        response
+            .headers
+            .insert("Date".to_string(), http_date(SystemTime::now()));
+    }
+    if let Some(name) = &server.server_header
+        && response.header("server").is_none()
+    {
+        response.headers.insert("Server".to_string(), name.clone());
+    }
+    // Every connection carries exactly one exchange, so say so
+    response.headers.insert("Connection", "close");
 }
 
 fn error_response(code: u16, req: &Request, handlers: &HashMap<u16, ErrorHandlerFn>) -> Response {
@@ -209,55 +1260,240 @@ fn error_response(code: u16, req: &Request, handlers: &HashMap<u16, ErrorHandler
     }
 }
 
-fn get_mime_type(path: &Path) -> &str {
-    match path.extension().and_then(|ext| ext.to_str()) {
-        Some("html") => "text/html",
-        Some("css") => "text/css",
-        Some("js") => "application/javascript",
-        Some("png") => "image/png",
-        Some("jpg") | Some("jpeg") => "image/jpeg",
-        Some("gif") => "image/gif",
-        Some("svg") => "image/svg+xml",
-        Some("json") => "application/json",
-        Some("pdf") => "application/pdf",
-        Some("txt") => "text/plain",
-        _ => "application/octet-stream",
+fn request_timeout() -> Response {
+    Response::new(408, b"408 Request Timeout".to_vec(), "text/plain")
+}
+
+const MAX_HEAD_SIZE: usize = 8192;
+
+const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+// Bytes asked of each read() of a request
+const READ_SIZE: usize = 64 * 1024;
+
+// Most buffer reserved up front for a body of announced length
+const MAX_PREALLOCATION: usize = 16 * 1024 * 1024;
+
+enum ReadError {
+    // Nothing, or only part of a head or body, arrived within the request timeout
+    TimedOut,
+    TooLarge,
+    // The client went away (or sent nothing at all)
+    Closed,
+}
+
+// Read until the blank line that ends the request head. Bytes of the body
+// that arrived in the same reads are returned too.
+fn read_head(stream: &mut TcpStream, timeout: Option<Duration>) -> Result<Vec<u8>, ReadError> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut data = Vec::new();
+    loop {
+        read_some(stream, &mut data, deadline)?;
+        match find_head_end(&data) {
+            Some(_) => return Ok(data),
+            None if data.len() > MAX_HEAD_SIZE => return Err(ReadError::TooLarge),
+            None => {}
+        }
+    }
+}
+
+// Read until `data` holds at least `len` bytes. Each read may take up to
+// `timeout`, so a slow but steady upload isn't cut off.
+fn read_to_len(
+    stream: &mut TcpStream,
+    data: &mut Vec<u8>,
+    len: usize,
+    timeout: Option<Duration>,
+) -> Result<(), ReadError> {
+    // Grow once rather than doubling (and copying) all the way up, but don't
+    // trust a huge Content-Length with memory before the bytes arrive
+    data.reserve(len.saturating_sub(data.len()).min(MAX_PREALLOCATION));
+    while data.len() < len {
+        read_some(stream, data, timeout.map(|t| Instant::now() + t))?;
+    }
+    Ok(())
+}
+
+// Append the bytes of one successful read(), read straight into `data`
+fn read_some(stream: &mut TcpStream, data: &mut Vec<u8>, deadline: Option<Instant>) -> Result<(), ReadError> {
+    let start = data.len();
+    data.resize(start + READ_SIZE, 0);
+    let result = loop {
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break Err(ReadError::TimedOut);
+            }
+            let _ = stream.set_read_timeout(Some(remaining));
+        }
+        match stream.read(&mut data[start..]) {
+            Ok(0) => break Err(ReadError::Closed),
+            Ok(n) => break Ok(n),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                break Err(ReadError::TimedOut);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break Err(ReadError::Closed),
+        }
+    };
+    data.truncate(start + *result.as_ref().unwrap_or(&0));
+    result.map(|_| ())
+}
+
+// Read the rest of the body. Malformed chunked framing is left for
+// extract_body to reject.
+fn read_body(
+    stream: &mut TcpStream,
+    server: &SimpleHttpServer,
+    framing: Framing,
+    mut expect_continue: bool,
+    head_len: usize,
+    received: &mut Vec<u8>,
+) -> Result<(), ReadError> {
+    let mut send_continue = |stream: &mut TcpStream| {
+        if std::mem::take(&mut expect_continue) {
+            let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+        }
+    };
+    match framing {
+        Framing::Chunked => {
+            while let Err(ChunkError::Incomplete) = chunked::message_len(&received[head_len..]) {
+                if server.max_body_size.is_some_and(|max| received.len() - head_len > max) {
+                    return Err(ReadError::TooLarge);
+                }
+                send_continue(stream);
+                read_some(stream, received, server.request_timeout.map(|t| Instant::now() + t))?;
+            }
+        }
+        Framing::Length(len) if received.len() < head_len + len => {
+            send_continue(stream);
+            read_to_len(stream, received, head_len + len, server.request_timeout)?;
+        }
+        Framing::Length(_) => {}
+    }
+    Ok(())
+}
+
+// Whether the route the request goes to reads its body itself
+fn streams_body(server: &SimpleHttpServer, request: &Request) -> bool {
+    matches!(
+        router_for(server, request).lookup_request(request),
+        RouteMatch::Found(route, _) if route.stream_body
+    )
+}
+
+// Transfer-Encoding whose final coding is chunked, so the length is known
+fn is_chunked(encoding: &str) -> bool {
+    encoding.rsplit(',').next().unwrap_or("").trim().eq_ignore_ascii_case("chunked")
+}
+
+// Offset just past the blank line that ends the request head
+fn find_head_end(data: &[u8]) -> Option<usize> {
+    data.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| pos + 4)
+}
+
+#[derive(Default)]
+struct Body {
+    data: Vec<u8>,
+    trailers: HashMap<String, String>,
+    // Bytes the body took up on the wire, framing included
+    consumed: usize,
+}
+
+// How the head says the body is delimited. Err carries the status code to
+// reply with.
+fn body_framing(server: &SimpleHttpServer, method: &str, headers: &HashMap<String, String>) -> Result<Framing, u16> {
+    if let Some(encoding) = headers.get("transfer-encoding") {
+        // Transfer-Encoding overrides any Content-Length (RFC 9112 section 6.3),
+        // and without chunked as the final coding the length can't be known
+        return if is_chunked(encoding) { Ok(Framing::Chunked) } else { Err(400) };
+    }
+    match headers.get("content-length") {
+        Some(value) => match parse_content_length(value) {
+            Some(len) if server.max_body_size.is_some_and(|max| len > max) => Err(413),
+            Some(len) => Ok(Framing::Length(len)),
+            None => Err(400),
+        },
+        None if server.require_content_length && matches!(method, "POST" | "PUT" | "PATCH") => {
+            Err(411)
+        }
+        None => Ok(Framing::Length(0)),
+    }
+}
+
+// Take exactly the body `framing` declares from the bytes that followed
+// the head. Err carries the status code to reply with.
+fn extract_body(framing: Framing, received: &[u8]) -> Result<Body, u16> {
+    match framing {
+        Framing::Chunked => {
+            let decoded = chunked::decode(received).map_err(|_| 400u16)?;
+            Ok(Body {
+                data: decoded.body,
+                trailers: decoded.trailers,
+                consumed: decoded.consumed,
+            })
+        }
+        Framing::Length(len) if received.len() < len => Err(400),
+        Framing::Length(len) => Ok(Body {
+            data: received[..len].to_vec(),
+            trailers: HashMap::new(),
+            consumed: len,
+        }),
+    }
+}
+
+// Digits only; a repeated header ("5, 5") is accepted when all values agree
+fn parse_content_length(value: &str) -> Option<usize> {
+    let mut len = None;
+    for part in value.split(',') {
+        let part = part.trim();
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let parsed: usize = part.parse().ok()?;
+        if len.is_some_and(|l| l != parsed) {
+            return None;
+        }
+        len = Some(parsed);
     }
+    len
 }
 
-fn parse_http_request(raw: &str) -> (String, String, HashMap<String, String>, Vec<u8>, HashMap<String, String>) {
-    let mut lines = raw.lines();
-    let request_line = lines.next().unwrap_or("");
+type ParsedRequest = (String, String, String, Vec<(String, String)>, HashMap<String, String>);
+
+// Works on the head's bytes (everything before the blank line), so the
+// body is never run through a UTF-8 conversion. Request line and header
+// values that aren't valid UTF-8 are decoded lossily.
+fn parse_http_request(head: &[u8]) -> ParsedRequest {
+    let mut lines = head
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let request_line = String::from_utf8_lossy(lines.next().unwrap_or(b""));
     let mut parts = request_line.split_whitespace();
     let method = parts.next().unwrap_or("GET").to_string();
-    let mut path = parts.next().unwrap_or("/").to_string();
+    let (authority, mut path) = split_request_target(parts.next().unwrap_or("/"));
+    let version = parts.next().unwrap_or("HTTP/1.1").to_ascii_uppercase();
 
     let mut query = HashMap::new();
     if let Some(pos) = path.find('?') {
-        let path_clone = path.clone();
-        let q = &path_clone[pos+1..];
-        path = path_clone[..pos].to_string();
-        for kv in q.split('&') {
-            let mut iter = kv.splitn(2, '=');
-            if let (Some(k), Some(v)) = (iter.next(), iter.next()) {
-                query.insert(k.to_string(), v.to_string());
-            }
-        }
+        query = parse_urlencoded(&path.as_bytes()[pos + 1..]);
+        path.truncate(pos);
     }
 
-    let mut headers = HashMap::new();
-    let mut body = Vec::new();
-    let mut in_body = false;
+    let mut headers = Vec::new();
     for line in lines {
-        if in_body {
-            body.extend_from_slice(line.as_bytes());
-            body.push(b'\n');
-        } else if line.is_empty() {
-            in_body = true;
-        } else if let Some((k, v)) = line.split_once(':') {
-            headers.insert(k.trim().to_ascii_lowercase(), v.trim().to_string());
-        }
+        let Some(colon) = line.iter().position(|&b| b == b':') else {
+            continue;
+        };
+        let name = String::from_utf8_lossy(&line[..colon]).trim().to_ascii_lowercase();
+        let value = String::from_utf8_lossy(&line[colon + 1..]).trim().to_string();
+        headers.push((name, value));
+    }
+    // The authority of an absolute-form target replaces any Host header (RFC 9112 section 3.2.2)
+    if let Some(authority) = authority {
+        headers.retain(|(name, _)| name != "host");
+        headers.push(("host".to_string(), authority));
     }
 
-    (method, path, headers, body, query)
+    (method, path, version, headers, query)
 }
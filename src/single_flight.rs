@@ -0,0 +1,86 @@
+use crate::response::Response;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+// One in-progress handler run that other requests can wait on
+#[derive(Default)]
+struct Call {
+    result: Mutex<Option<Outcome>>,
+    done: Condvar,
+}
+
+enum Outcome {
+    Shared(Response),
+    // The leader's body is streamed and can't be shared, so each waiter
+    // runs the handler itself
+    RunOwn,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct SingleFlight {
+    calls: Arc<Mutex<HashMap<String, Arc<Call>>>>,
+}
+
+// Publishes a result for waiters even if the leader's handler panics
+struct LeaderGuard<'a> {
+    flight: &'a SingleFlight,
+    key: &'a str,
+    call: Arc<Call>,
+}
+
+impl Drop for LeaderGuard<'_> {
+    fn drop(&mut self) {
+        self.flight.calls.lock().unwrap().remove(self.key);
+        let mut result = self.call.result.lock().unwrap_or_else(|e| e.into_inner());
+        if result.is_none() {
+            *result = Some(Outcome::Shared(Response::new(500, b"500 Error".to_vec(), "text/plain")));
+        }
+        self.call.done.notify_all();
+    }
+}
+
+impl SingleFlight {
+    // The first caller for `key` runs `f`; callers arriving meanwhile get a copy of its response
+    pub(crate) fn run(&self, key: &str, f: impl FnOnce() -> Response) -> Response {
+        let (call, leader) = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(key) {
+                Some(call) => (Arc::clone(call), false),
+                None => {
+                    let call = Arc::new(Call::default());
+                    calls.insert(key.to_string(), Arc::clone(&call));
+                    (call, true)
+                }
+            }
+        };
+
+        if !leader {
+            let mut result = call.result.lock().unwrap();
+            while result.is_none() {
+                result = call.done.wait(result).unwrap();
+            }
+            return match result.as_ref().unwrap() {
+                Outcome::Shared(response) => response.clone(),
+                Outcome::RunOwn => {
+                    drop(result);
+                    f()
+                }
+            };
+        }
+
+        let guard = LeaderGuard {
+            flight: self,
+            key,
+            call: Arc::clone(&call),
+        };
+        let response = f();
+        let outcome = match response.is_streamed() {
+            true => Outcome::RunOwn,
+            false => Outcome::Shared(response.clone()),
+        };
+        *call.result.lock().unwrap() = Some(outcome);
+        drop(guard);
+        response
+    }
+}
@@ -0,0 +1,351 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub type AcceptHook = Arc<dyn Fn(&TcpStream) + Send + Sync>;
+
+// TCP tuning applied to every accepted connection
+#[derive(Clone, Default, Debug)]
+pub struct SocketOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<Keepalive>,
+    linger: Option<Duration>,
+    recv_buffer: Option<usize>,
+    send_buffer: Option<usize>,
+    ttl: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Keepalive {
+    idle: Duration,
+    interval: Option<Duration>,
+    retries: Option<u32>,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // TCP_NODELAY: disable Nagle's algorithm
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = Some(enabled);
+        self
+    }
+
+    // SO_KEEPALIVE, first probe after `idle` without traffic
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(Keepalive {
+            idle,
+            interval: None,
+            retries: None,
+        });
+        self
+    }
+
+    // Probe spacing and count once keepalive probing has started
+    pub fn keepalive_probes(mut self, interval: Duration, retries: u32) -> Self {
+        let keepalive = self.keepalive.get_or_insert(Keepalive {
+            idle: interval,
+            interval: None,
+            retries: None,
+        });
+        keepalive.interval = Some(interval);
+        keepalive.retries = Some(retries);
+        self
+    }
+
+    // SO_LINGER: block close() up to `timeout` to flush unsent data
+    pub fn linger(mut self, timeout: Duration) -> Self {
+        self.linger = Some(timeout);
+        self
+    }
+
+    // SO_RCVBUF
+    pub fn recv_buffer_size(mut self, bytes: usize) -> Self {
+        self.recv_buffer = Some(bytes);
+        self
+    }
+
+    // SO_SNDBUF
+    pub fn send_buffer_size(mut self, bytes: usize) -> Self {
+        self.send_buffer = Some(bytes);
+        self
+    }
+
+    // IP_TTL
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            stream.set_nodelay(nodelay)?;
+        }
+        if let Some(ttl) = self.ttl {
+            stream.set_ttl(ttl)?;
+        }
+        if self.keepalive.is_none()
+            && self.linger.is_none()
+            && self.recv_buffer.is_none()
+            && self.send_buffer.is_none()
+        {
+            return Ok(());
+        }
+        sys::apply(self, stream)
+    }
+}
+
+// Bind like TcpListener::bind, but when `v6only` is set and the address is
+// IPv6, set IPV6_V6ONLY before binding. Otherwise the OS default applies
+// (dual-stack on Linux, so "[::]:8080" also accepts IPv4 clients).
+pub(crate) fn bind_listener(addr: &str, v6only: Option<bool>) -> io::Result<TcpListener> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        let result = match (addr, v6only) {
+            (SocketAddr::V6(_), Some(only)) => sys::bind_v6(addr, only),
+            _ => TcpListener::bind(addr),
+        };
+        match result {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+    }))
+}
+
+// The constants below are written out per platform rather than taken from
+// libc. Linux ones are the asm-generic values, which MIPS, SPARC, Alpha and
+// PA-RISC don't share, so other architectures get the fallback.
+#[cfg(any(
+    all(
+        any(target_os = "linux", target_os = "android"),
+        any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "arm",
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "loongarch64"
+        )
+    ),
+    target_os = "macos",
+    target_os = "ios"
+))]
+mod sys {
+    use super::SocketOptions;
+
+    use std::ffi::{c_int, c_void};
+    use std::io;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    mod consts {
+        use std::ffi::c_int;
+        pub const SOL_SOCKET: c_int = 1;
+        pub const SO_KEEPALIVE: c_int = 9;
+        pub const SO_LINGER: c_int = 13;
+        pub const SO_RCVBUF: c_int = 8;
+        pub const SO_SNDBUF: c_int = 7;
+        pub const IPPROTO_TCP: c_int = 6;
+        pub const TCP_KEEPIDLE: c_int = 4;
+        pub const TCP_KEEPINTVL: c_int = 5;
+        pub const TCP_KEEPCNT: c_int = 6;
+        pub const SO_REUSEADDR: c_int = 2;
+        pub const AF_INET6: c_int = 10;
+        pub const SOCK_STREAM: c_int = 1 | 0o2000000; // SOCK_CLOEXEC
+        pub const IPPROTO_IPV6: c_int = 41;
+        pub const IPV6_V6ONLY: c_int = 26;
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    mod consts {
+        use std::ffi::c_int;
+        pub const SOL_SOCKET: c_int = 0xffff;
+        pub const SO_KEEPALIVE: c_int = 0x0008;
+        // SO_LINGER_SEC: linger time in seconds rather than clock ticks
+        pub const SO_LINGER: c_int = 0x1080;
+        pub const SO_RCVBUF: c_int = 0x1002;
+        pub const SO_SNDBUF: c_int = 0x1001;
+        pub const IPPROTO_TCP: c_int = 6;
+        pub const TCP_KEEPIDLE: c_int = 0x10;
+        pub const TCP_KEEPINTVL: c_int = 0x101;
+        pub const TCP_KEEPCNT: c_int = 0x102;
+        pub const SO_REUSEADDR: c_int = 0x0004;
+        pub const AF_INET6: c_int = 30;
+        pub const SOCK_STREAM: c_int = 1;
+        pub const IPPROTO_IPV6: c_int = 41;
+        pub const IPV6_V6ONLY: c_int = 27;
+    }
+
+    use consts::*;
+
+    #[repr(C)]
+    struct Linger {
+        l_onoff: c_int,
+        l_linger: c_int,
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[repr(C)]
+    struct SockaddrIn6 {
+        sin6_family: u16,
+        sin6_port: u16,
+        sin6_flowinfo: u32,
+        sin6_addr: [u8; 16],
+        sin6_scope_id: u32,
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    #[repr(C)]
+    struct SockaddrIn6 {
+        sin6_len: u8,
+        sin6_family: u8,
+        sin6_port: u16,
+        sin6_flowinfo: u32,
+        sin6_addr: [u8; 16],
+        sin6_scope_id: u32,
+    }
+
+    unsafe extern "C" {
+        fn setsockopt(fd: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+        fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+        fn bind(fd: c_int, addr: *const c_void, len: u32) -> c_int;
+        fn listen(fd: c_int, backlog: c_int) -> c_int;
+        fn close(fd: c_int) -> c_int;
+    }
+
+    fn check(ret: c_int) -> io::Result<c_int> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    pub(super) fn bind_v6(addr: SocketAddr, v6only: bool) -> io::Result<TcpListener> {
+        let SocketAddr::V6(v6) = addr else {
+            return TcpListener::bind(addr);
+        };
+        // SAFETY: plain socket syscalls on a descriptor we own until it is
+        // handed to TcpListener; every failure path closes it
+        unsafe {
+            let fd = check(socket(AF_INET6, SOCK_STREAM, 0))?;
+            let result = (|| {
+                set(fd, SOL_SOCKET, SO_REUSEADDR, &(1 as c_int))?;
+                set(fd, IPPROTO_IPV6, IPV6_V6ONLY, &(v6only as c_int))?;
+                let sockaddr = SockaddrIn6 {
+                    #[cfg(any(target_os = "macos", target_os = "ios"))]
+                    sin6_len: std::mem::size_of::<SockaddrIn6>() as u8,
+                    sin6_family: AF_INET6 as _,
+                    sin6_port: v6.port().to_be(),
+                    sin6_flowinfo: v6.flowinfo(),
+                    sin6_addr: v6.ip().octets(),
+                    sin6_scope_id: v6.scope_id(),
+                };
+                check(bind(
+                    fd,
+                    &sockaddr as *const SockaddrIn6 as *const c_void,
+                    std::mem::size_of::<SockaddrIn6>() as u32,
+                ))?;
+                check(listen(fd, 128))?;
+                Ok(())
+            })();
+            match result {
+                Ok(()) => Ok(TcpListener::from_raw_fd(fd)),
+                Err(e) => {
+                    close(fd);
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    fn set<T>(fd: c_int, level: c_int, name: c_int, value: &T) -> io::Result<()> {
+        // SAFETY: `value` points to a live T and we pass its exact size
+        let ret = unsafe {
+            setsockopt(
+                fd,
+                level,
+                name,
+                value as *const T as *const c_void,
+                std::mem::size_of::<T>() as u32,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn secs(d: std::time::Duration) -> c_int {
+        d.as_secs().clamp(1, c_int::MAX as u64) as c_int
+    }
+
+    pub(super) fn apply(opts: &SocketOptions, stream: &TcpStream) -> io::Result<()> {
+        let fd = stream.as_raw_fd();
+        if let Some(keepalive) = opts.keepalive {
+            set(fd, SOL_SOCKET, SO_KEEPALIVE, &(1 as c_int))?;
+            set(fd, IPPROTO_TCP, TCP_KEEPIDLE, &secs(keepalive.idle))?;
+            if let Some(interval) = keepalive.interval {
+                set(fd, IPPROTO_TCP, TCP_KEEPINTVL, &secs(interval))?;
+            }
+            if let Some(retries) = keepalive.retries {
+                set(fd, IPPROTO_TCP, TCP_KEEPCNT, &(retries as c_int))?;
+            }
+        }
+        if let Some(linger) = opts.linger {
+            let value = Linger {
+                l_onoff: 1,
+                l_linger: linger.as_secs().min(c_int::MAX as u64) as c_int,
+            };
+            set(fd, SOL_SOCKET, SO_LINGER, &value)?;
+        }
+        if let Some(size) = opts.recv_buffer {
+            set(fd, SOL_SOCKET, SO_RCVBUF, &(size.min(c_int::MAX as usize) as c_int))?;
+        }
+        if let Some(size) = opts.send_buffer {
+            set(fd, SOL_SOCKET, SO_SNDBUF, &(size.min(c_int::MAX as usize) as c_int))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(
+    all(
+        any(target_os = "linux", target_os = "android"),
+        any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "arm",
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "loongarch64"
+        )
+    ),
+    target_os = "macos",
+    target_os = "ios"
+)))]
+mod sys {
+    use super::SocketOptions;
+
+    use std::io;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+
+    pub(super) fn bind_v6(addr: SocketAddr, _v6only: bool) -> io::Result<TcpListener> {
+        TcpListener::bind(addr)
+    }
+
+    pub(super) fn apply(_opts: &SocketOptions, _stream: &TcpStream) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "keepalive, linger and buffer sizes are not supported on this platform",
+        ))
+    }
+}
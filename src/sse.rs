@@ -0,0 +1,191 @@
+// Server-Sent Events: a response that stays open while the server pushes
+// `text/event-stream` frames to it
+//
+//     let (stream, events) = SseStream::channel();
+//     thread::spawn(move || {
+//         while events.send(SseEvent::new(&load()).event("load")).is_ok() {
+//             thread::sleep(Duration::from_secs(1));
+//         }
+//     });
+//     stream.into_response()
+//
+// The stream ends when every SseSender is dropped; send() fails once the
+// client has gone. Each open stream holds its connection's thread until
+// then. A reconnecting client's last seen id is in req.header("last-event-id").
+
+use crate::response::{IntoResponse, Response};
+
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+// How long a quiet stream waits before sending a comment line, so proxies
+// and browsers don't time the connection out
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+
+// One event frame
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    event: Option<String>,
+    data: String,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl SseEvent {
+    // An unnamed ("message") event; multi-line data is sent line by line
+    pub fn new(data: &str) -> Self {
+        Self {
+            data: data.to_string(),
+            ..Self::default()
+        }
+    }
+
+    // Event type, for addEventListener on the client
+    pub fn event(mut self, name: &str) -> Self {
+        self.event = Some(single_line(name));
+        self
+    }
+
+    // Sent back as Last-Event-ID when the client reconnects
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    // How long the client waits before reconnecting
+    pub fn retry(mut self, delay: Duration) -> Self {
+        self.retry = Some(delay);
+        self
+    }
+
+    fn to_frame(&self) -> Vec<u8> {
+        let mut frame = String::new();
+        if let Some(event) = &self.event {
+            frame.push_str(&format!("event: {}\n", event));
+        }
+        if let Some(id) = &self.id {
+            frame.push_str(&format!("id: {}\n", id));
+        }
+        if let Some(retry) = self.retry {
+            frame.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split('\n') {
+            frame.push_str(&format!("data: {}\n", line.strip_suffix('\r').unwrap_or(line)));
+        }
+        frame.push('\n');
+        frame.into_bytes()
+    }
+}
+
+// A line break in a field would end it early and start another
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+// The client disconnected (or the response was never sent)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SseClosed;
+
+impl fmt::Display for SseClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("event stream closed")
+    }
+}
+
+impl std::error::Error for SseClosed {}
+
+// Pushes events to one stream; clone it to push from several threads
+#[derive(Clone)]
+pub struct SseSender {
+    sender: Sender<Vec<u8>>,
+}
+
+impl SseSender {
+    pub fn send(&self, event: SseEvent) -> Result<(), SseClosed> {
+        self.sender.send(event.to_frame()).map_err(|_| SseClosed)
+    }
+
+    // A comment line, ignored by clients
+    pub fn comment(&self, text: &str) -> Result<(), SseClosed> {
+        self.sender
+            .send(format!(": {}\n\n", single_line(text)).into_bytes())
+            .map_err(|_| SseClosed)
+    }
+}
+
+// The receiving end, turned into the handler's response
+pub struct SseStream {
+    receiver: Receiver<Vec<u8>>,
+    heartbeat: Option<Duration>,
+}
+
+impl SseStream {
+    pub fn channel() -> (SseStream, SseSender) {
+        let (sender, receiver) = mpsc::channel();
+        let stream = SseStream {
+            receiver,
+            heartbeat: Some(DEFAULT_HEARTBEAT),
+        };
+        (stream, SseSender { sender })
+    }
+
+    // Interval for keep-alive comments while no events arrive (15s by
+    // default); None sends none
+    pub fn heartbeat(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    pub fn into_response(self) -> Response {
+        Response::stream(
+            SseReader {
+                stream: self,
+                frame: Vec::new(),
+                pos: 0,
+            },
+            "text/event-stream",
+        )
+        .with_header("Cache-Control", "no-cache")
+        // Stop nginx from holding events back in its buffer
+        .with_header("X-Accel-Buffering", "no")
+    }
+}
+
+impl IntoResponse for SseStream {
+    fn into_response(self) -> Response {
+        SseStream::into_response(self)
+    }
+}
+
+// Frames as they are sent, for the streamed response body
+struct SseReader {
+    stream: SseStream,
+    frame: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for SseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.frame.len() {
+            let next = match self.stream.heartbeat {
+                Some(interval) => match self.stream.receiver.recv_timeout(interval) {
+                    Ok(frame) => Some(frame),
+                    Err(RecvTimeoutError::Timeout) => Some(b": keep-alive\n\n".to_vec()),
+                    Err(RecvTimeoutError::Disconnected) => None,
+                },
+                None => self.stream.receiver.recv().ok(),
+            };
+            let Some(frame) = next else {
+                return Ok(0);
+            };
+            self.frame = frame;
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.frame.len() - self.pos);
+        buf[..n].copy_from_slice(&self.frame[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
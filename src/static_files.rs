@@ -0,0 +1,340 @@
+use crate::archive::Archive;
+#[cfg(feature = "brotli")]
+use crate::compress::brotli;
+use crate::compress::gzip;
+use crate::concurrency::not_modified;
+use crate::http_date::http_date;
+use crate::request::Request;
+use crate::response::Response;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bodies smaller than this rarely shrink enough to be worth compressing
+pub(crate) const MIN_COMPRESS_SIZE: u64 = 256;
+
+// More ranges than this in one request are ignored and the full file sent
+const MAX_RANGES: usize = 32;
+
+// Serve a file from `dir`, preferring an up-to-date precompressed sibling
+// (`.br`, then `.gz`) when the client accepts that encoding
+pub(crate) fn serve(dir: &str, req: &Request) -> Option<Response> {
+    serve_file(&static_path(Path::new(dir), &req.path)?, req)
+}
+
+// The file for `request_path` under `dir`. None if a segment could lead
+// outside it: `..`, an empty segment, or one with a backslash, NUL or
+// drive colon that some platform reads as a separator.
+fn static_path(dir: &Path, request_path: &str) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+    for segment in request_path.trim_start_matches('/').split('/') {
+        if segment.is_empty()
+            || segment == "."
+            || segment == ".."
+            || segment.contains(['\\', '\0', ':'])
+        {
+            return None;
+        }
+        path.push(segment);
+    }
+    Some(path)
+}
+
+pub(crate) fn serve_file(full_path: &Path, req: &Request) -> Option<Response> {
+    let metadata = fs::metadata(full_path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let content_type = get_mime_type(full_path);
+    let accept_encoding = req.header("accept-encoding").map(|s| s.as_str()).unwrap_or("");
+
+    for (encoding, ext) in [("br", "br"), ("gzip", "gz")] {
+        if !accepts_encoding(accept_encoding, encoding) {
+            continue;
+        }
+        let sibling = sibling_path(full_path, ext);
+        if is_up_to_date(&sibling, full_path)
+            && let Ok(contents) = fs::read(&sibling)
+        {
+            let response = Response::new(200, contents, content_type)
+                .with_header("Content-Encoding", encoding)
+                .with_header("Vary", "Accept-Encoding");
+            return Some(finish(req, response, metadata.modified().ok()));
+        }
+    }
+
+    let contents = fs::read(full_path).ok()?;
+    let response = Response::new(200, contents, content_type);
+    Some(finish(req, response, metadata.modified().ok()))
+}
+
+// Serve a file from an archive mounted at `prefix`
+pub(crate) fn serve_archive(prefix: &str, archive: &Archive, req: &Request) -> Option<Response> {
+    let name = strip_mount(prefix, &req.path)?;
+    let file = archive.read(name)?;
+    let response = Response::new(200, file.contents, get_mime_type(Path::new(name)));
+    Some(finish(req, response, file.modified))
+}
+
+// The part of `path` below a mount point, without a leading slash.
+// None if `path` isn't under `prefix`.
+pub(crate) fn strip_mount<'a>(prefix: &str, path: &'a str) -> Option<&'a str> {
+    let prefix = prefix.trim_end_matches('/');
+    let rest = path.strip_prefix(prefix)?;
+    if !prefix.is_empty() && !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    Some(rest.trim_start_matches('/'))
+}
+
+// Caching and range headers shared by every static source
+fn finish(req: &Request, mut response: Response, modified: Option<SystemTime>) -> Response {
+    if let Some(modified) = modified {
+        response = response.with_header("Last-Modified", &http_date(modified));
+    }
+    response = response.with_header("Accept-Ranges", "bytes");
+    if not_modified(req, None, modified) {
+        response.status_code = 304;
+        response.body = Vec::new();
+        return response;
+    }
+
+    let total = response.body.len() as u64;
+    match req.range(total) {
+        None => response,
+        Some(ranges) if ranges.is_empty() => {
+            let mut unsatisfiable = Response::new(416, Vec::new(), "text/plain");
+            unsatisfiable.headers = response.headers;
+            unsatisfiable.with_header("Content-Range", &format!("bytes */{}", total))
+        }
+        Some(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            response.status_code = 206;
+            response.body = response.body[start as usize..=end as usize].to_vec();
+            response.with_header("Content-Range", &format!("bytes {}-{}/{}", start, end, total))
+        }
+        Some(ranges) => {
+            let boundary = boundary();
+            let mut body = Vec::new();
+            for (start, end) in ranges {
+                body.extend_from_slice(
+                    format!(
+                        "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, response.content_type, start, end, total
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(&response.body[start as usize..=end as usize]);
+                body.extend_from_slice(b"\r\n");
+            }
+            body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+            response.status_code = 206;
+            response.body = body;
+            response.content_type = format!("multipart/byteranges; boundary={}", boundary);
+            response
+        }
+    }
+}
+
+// Resolve a "bytes=" Range header against `total`, dropping unsatisfiable
+// ranges and merging ones that overlap or touch. None means the header
+// should be ignored and the full body served; an empty list means nothing
+// was satisfiable (416).
+pub(crate) fn parse_ranges(header: &str, total: u64) -> Option<Vec<(u64, u64)>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let specs: Vec<&str> = spec.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+    // Many tiny ranges cost more to answer than the whole file
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            // Suffix range: the last N bytes
+            let suffix: u64 = end.parse().ok()?;
+            if suffix > 0 && total > 0 {
+                ranges.push((total.saturating_sub(suffix), total - 1));
+            }
+            continue;
+        }
+        let start: u64 = start.parse().ok()?;
+        let end = if end.is_empty() {
+            total.saturating_sub(1)
+        } else {
+            let end: u64 = end.parse().ok()?;
+            if end < start {
+                return None;
+            }
+            end.min(total.saturating_sub(1))
+        };
+        if start < total {
+            ranges.push((start, end));
+        }
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(merged)
+}
+
+// Separator for multipart/byteranges parts; only has to be absent from the
+// body, which a random-looking 64-bit value practically guarantees
+fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mixed = (nanos ^ n.rotate_left(32)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    format!("rake-{:016x}", mixed)
+}
+
+pub(crate) type Compressor = fn(&[u8]) -> Vec<u8>;
+
+// Sibling extensions written by precompress_dir and how to produce them
+#[cfg(feature = "brotli")]
+const PRECOMPRESSED: &[(&str, Compressor)] = &[("gz", gzip), ("br", brotli)];
+#[cfg(not(feature = "brotli"))]
+const PRECOMPRESSED: &[(&str, Compressor)] = &[("gz", gzip)];
+
+// Walk `dir` and write `.gz` (and with the brotli feature `.br`) siblings
+// for compressible files whose sibling is missing or older than the
+// source. Returns how many were written.
+pub(crate) fn precompress_dir(dir: &Path) -> io::Result<usize> {
+    let mut written = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            written += precompress_dir(&path)?;
+            continue;
+        }
+        if !is_compressible(&path) {
+            continue;
+        }
+        let mut contents = None;
+        for (ext, compress) in PRECOMPRESSED {
+            let sibling = sibling_path(&path, ext);
+            if is_up_to_date(&sibling, &path) {
+                continue;
+            }
+            let contents = match &contents {
+                Some(contents) => contents,
+                None => contents.insert(fs::read(&path)?),
+            };
+            let compressed = compress(contents);
+            // Not worth serving if it didn't shrink
+            if compressed.len() < contents.len() {
+                fs::write(&sibling, compressed)?;
+                written += 1;
+            }
+        }
+    }
+    Ok(written)
+}
+
+fn is_compressible(path: &Path) -> bool {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size < MIN_COMPRESS_SIZE {
+        return false;
+    }
+    is_compressible_type(get_mime_type(path))
+}
+
+// Text formats worth compressing; `mime` has no parameters
+pub(crate) fn is_compressible_type(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/javascript" | "application/json" | "image/svg+xml" | "application/xml"
+        )
+}
+
+fn sibling_path(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+// A sibling is only trusted if it was written after the source last changed
+fn is_up_to_date(sibling: &Path, source: &Path) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(sibling), modified(source)) {
+        (Some(sibling), Some(source)) => sibling >= source,
+        _ => false,
+    }
+}
+
+// True if `encoding` is listed (or covered by `*`) without q=0
+pub(crate) fn accepts_encoding(header: &str, encoding: &str) -> bool {
+    let mut wildcard = false;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let q = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding) {
+            return q > 0.0;
+        }
+        if name == "*" {
+            wildcard = q > 0.0;
+        }
+    }
+    wildcard
+}
+
+pub(crate) fn get_mime_type(path: &Path) -> &str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("json") => "application/json",
+        Some("pdf") => "application/pdf",
+        Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_path_stays_inside_the_dir() {
+        let dir = Path::new("public");
+        assert_eq!(static_path(dir, "/css/site.css"), Some(dir.join("css").join("site.css")));
+        assert_eq!(static_path(dir, "/.well-known/x"), Some(dir.join(".well-known").join("x")));
+        for path in [
+            "/../secret.txt",
+            "/css/../../secret.txt",
+            "/css/./site.css",
+            "/css//site.css",
+            "/css/",
+            "/",
+            "/..\\secret.txt",
+            "/C:/secret.txt",
+            "/a\0b",
+        ] {
+            assert_eq!(static_path(dir, path), None, "{}", path);
+        }
+    }
+}
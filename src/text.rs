@@ -0,0 +1,96 @@
+// Text request bodies decoded by their declared charset
+
+use crate::error::error_response;
+use crate::request::Request;
+
+use std::fmt;
+
+// What 0x80..=0x9F mean in windows-1252, which browsers also use for
+// bodies labelled iso-8859-1; the five unassigned bytes map to the C1
+// control of the same value as in Latin-1
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TextError {
+    // Content-Type names a charset this server can't decode
+    UnsupportedCharset(String),
+    // The body isn't valid in its charset
+    Invalid(String),
+}
+
+impl TextError {
+    // 415 for a charset there is no decoder for, 400 for a body that
+    // isn't valid in its charset
+    pub fn status(&self) -> u16 {
+        match self {
+            TextError::UnsupportedCharset(_) => 415,
+            TextError::Invalid(_) => 400,
+        }
+    }
+
+}
+
+error_response!(TextError);
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextError::UnsupportedCharset(charset) => write!(f, "unsupported charset {}", charset),
+            TextError::Invalid(charset) => write!(f, "request body is not valid {}", charset),
+        }
+    }
+}
+
+impl std::error::Error for TextError {}
+
+impl Request {
+    // The body as text in the Content-Type charset: UTF-8 (the default),
+    // US-ASCII, ISO-8859-1/windows-1252 or UTF-16 (BE, LE, or by BOM)
+    pub fn text(&self) -> Result<String, TextError> {
+        let charset = self
+            .content_type()
+            .and_then(|mime| mime.charset().map(str::to_ascii_lowercase))
+            .unwrap_or_else(|| "utf-8".to_string());
+        decode(&self.body, &charset)
+    }
+}
+
+fn decode(body: &[u8], charset: &str) -> Result<String, TextError> {
+    let invalid = || TextError::Invalid(charset.to_string());
+    match charset {
+        "utf-8" | "utf8" => {
+            let body = body.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(body);
+            String::from_utf8(body.to_vec()).map_err(|_| invalid())
+        }
+        "us-ascii" | "ascii" => match body.is_ascii() {
+            true => Ok(String::from_utf8_lossy(body).into_owned()),
+            false => Err(invalid()),
+        },
+        "iso-8859-1" | "latin1" | "latin-1" | "l1" | "windows-1252" | "cp1252" => Ok(body
+            .iter()
+            .map(|&b| match b {
+                0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                _ => b as char,
+            })
+            .collect()),
+        "utf-16" | "utf-16be" | "utf-16le" => {
+            let (big_endian, body) = match body {
+                [0xFE, 0xFF, rest @ ..] if charset == "utf-16" => (true, rest),
+                [0xFF, 0xFE, rest @ ..] if charset == "utf-16" => (false, rest),
+                _ => (charset != "utf-16le", body),
+            };
+            if body.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            let units = body.chunks_exact(2).map(|pair| match big_endian {
+                true => u16::from_be_bytes([pair[0], pair[1]]),
+                false => u16::from_le_bytes([pair[0], pair[1]]),
+            });
+            char::decode_utf16(units).collect::<Result<String, _>>().map_err(|_| invalid())
+        }
+        _ => Err(TextError::UnsupportedCharset(charset.to_string())),
+    }
+}
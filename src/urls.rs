@@ -0,0 +1,218 @@
+// Named routes and reverse URL generation. Each router keeps its own names;
+// handlers reach the ones of the router serving them through
+// req.url_for() and req.expand_urls().
+
+use crate::router::matches_type;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Route patterns by name. Shared, since every request carries its router's.
+#[derive(Clone, Default)]
+pub(crate) struct NamedRoutes(Arc<HashMap<String, String>>);
+
+impl NamedRoutes {
+    // Panics if `name` is already taken by a different pattern
+    pub(crate) fn register(&mut self, name: &str, pattern: &str) {
+        if let Some(existing) = self.0.get(name) {
+            assert!(
+                existing == pattern,
+                "route name `{}` is already used for {}",
+                name,
+                existing
+            );
+        }
+        Arc::make_mut(&mut self.0).insert(name.to_string(), pattern.to_string());
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
+
+    pub(crate) fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        build_url(self.0.get(name)?, params)
+    }
+
+    pub(crate) fn expand_urls(&self, template: &str) -> String {
+        expand_urls(template, |name, params| self.url_for(name, params))
+    }
+}
+
+// `pattern` with its parameters filled in. Parameters the pattern doesn't
+// use become the query string. None if a parameter is missing or a value
+// doesn't fit its <name:type>.
+fn build_url(pattern: &str, params: &[(&str, &str)]) -> Option<String> {
+    let mut used = Vec::new();
+    let mut segments = Vec::new();
+    for segment in pattern.trim_matches('/').split('/') {
+        let Some(param) = segment.strip_prefix('<').and_then(|s| s.strip_suffix('>')) else {
+            segments.push(segment.to_string());
+            continue;
+        };
+        if let Some(rest) = param.strip_suffix("..") {
+            let value = lookup(params, rest)?;
+            used.push(rest);
+            segments.push(encode_path(value));
+            continue;
+        }
+        let (param, kind) = param.split_once(':').unwrap_or((param, "str"));
+        let value = lookup(params, param)?;
+        if !matches_type(kind, value) {
+            return None;
+        }
+        used.push(param);
+        segments.push(encode(value));
+    }
+
+    let mut url = format!("/{}", segments.join("/"));
+    let query: Vec<String> = params
+        .iter()
+        .filter(|(key, _)| !used.contains(key))
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect();
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(&query.join("&"));
+    }
+    Some(url)
+}
+
+// Replace `{{ url_for("user_detail", id=42) }}` placeholders using
+// `url_for`. Unknown routes render as an empty string.
+fn expand_urls<F>(template: &str, url_for: F) -> String
+where
+    F: Fn(&str, &[(&str, &str)]) -> Option<String>,
+{
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let inner = after[2..end].trim();
+        match inner.strip_prefix("url_for(").and_then(|s| s.strip_suffix(')')) {
+            Some(args) => out.push_str(&expand_call(args, &url_for).unwrap_or_default()),
+            None => out.push_str(&after[..end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// `"name", key=value, key="value"`
+fn expand_call<F>(args: &str, url_for: F) -> Option<String>
+where
+    F: Fn(&str, &[(&str, &str)]) -> Option<String>,
+{
+    let mut args = args.split(',').map(str::trim);
+    let name = args.next()?.trim_matches('"');
+    let params: Vec<(&str, &str)> = args
+        .filter_map(|arg| arg.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim().trim_matches('"')))
+        .collect();
+    url_for(name, &params)
+}
+
+fn lookup<'a>(params: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    params.iter().find(|(key, _)| *key == name).map(|(_, value)| *value)
+}
+
+// Percent-encode each segment of a path, keeping the slashes between them
+pub(crate) fn encode_path(value: &str) -> String {
+    let parts: Vec<String> = value.split('/').map(encode).collect();
+    parts.join("/")
+}
+
+// Percent-encode everything but RFC 3986 unreserved characters
+fn encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+// Undo %XX escapes in a path segment. Malformed escapes are kept as they
+// are, and so is the whole segment if the result isn't UTF-8.
+pub(crate) fn percent_decode(value: &str) -> Cow<'_, str> {
+    if !value.contains('%') {
+        return Cow::Borrowed(value);
+    }
+    match String::from_utf8(unescape(value.as_bytes())) {
+        Ok(decoded) => Cow::Owned(decoded),
+        Err(_) => Cow::Borrowed(value),
+    }
+}
+
+// Decode an application/x-www-form-urlencoded key or value (query strings
+// and form bodies): `+` is a space and %XX escapes are undone, with
+// invalid UTF-8 replaced
+pub(crate) fn form_decode(value: &str) -> String {
+    let value = value.replace('+', " ");
+    if !value.contains('%') {
+        return value;
+    }
+    String::from_utf8_lossy(&unescape(value.as_bytes())).into_owned()
+}
+
+fn unescape(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i..i + 3) {
+            Some(&[b'%', high, low]) => hex_value(high).zip(hex_value(low)),
+            _ => None,
+        };
+        match escaped {
+            Some((high, low)) => {
+                out.push(high << 4 | low);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+// Only the two hex digits themselves; from_str_radix would also take `+1`
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn form_decode_handles_plus_and_escapes() {
+        assert_eq!(form_decode("a+b"), "a b");
+        assert_eq!(form_decode("a%2Bb"), "a+b");
+        assert_eq!(form_decode("%e2%82%ac"), "€");
+        assert_eq!(form_decode("100%"), "100%");
+        assert_eq!(form_decode("%zz%4"), "%zz%4");
+        assert_eq!(form_decode("%+1"), "% 1");
+    }
+
+    #[test]
+    fn percent_decode_needs_two_hex_digits() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("a+b"), "a+b");
+        assert_eq!(percent_decode("%+1"), "%+1");
+        assert_eq!(percent_decode("%-1"), "%-1");
+        assert_eq!(percent_decode("%1"), "%1");
+        assert_eq!(percent_decode("%C3%A9"), "é");
+        // Not UTF-8 once decoded, so left alone
+        assert_eq!(percent_decode("%FF"), "%FF");
+    }
+}
@@ -0,0 +1,212 @@
+// Declarative validation of form (or JSON) input:
+//
+//     let form = Validator::new()
+//         .field("name", vec![Rule::Required, Rule::MaxLength(50)])
+//         .field("email", vec![Rule::Required, Rule::Email])
+//         .field("age", vec![Rule::Range(18.0, 130.0)])
+//         .check(&req.form_data());
+//
+// `check` gives the declared fields that were sent, or every failed rule
+// as a FieldError list to show next to the form or send as a 422.
+
+use crate::regex::Regex;
+use crate::response::{IntoResponse, Response};
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone)]
+pub enum Rule {
+    // Present and not just whitespace
+    Required,
+    // Length in characters
+    MinLength(usize),
+    MaxLength(usize),
+    // The whole value must match
+    Pattern(Regex),
+    // local@domain.tld shape; whether it can receive mail is not checked
+    Email,
+    // Parses as a number
+    Numeric,
+    // A number within min..=max
+    Range(f64, f64),
+}
+
+impl Rule {
+    // None if `value` passes, otherwise why not
+    fn check(&self, value: &str) -> Option<String> {
+        let number = || value.trim().parse::<f64>().ok().filter(|n| n.is_finite());
+        match self {
+            Rule::Required => value.trim().is_empty().then(|| "is required".to_string()),
+            Rule::MinLength(min) => {
+                (value.chars().count() < *min).then(|| format!("must be at least {} characters", min))
+            }
+            Rule::MaxLength(max) => {
+                (value.chars().count() > *max).then(|| format!("must be at most {} characters", max))
+            }
+            Rule::Pattern(regex) => {
+                (!regex.is_full_match(value)).then(|| format!("must match {}", regex.as_str()))
+            }
+            Rule::Email => (!is_email(value)).then(|| "must be an email address".to_string()),
+            Rule::Numeric => number().is_none().then(|| "must be a number".to_string()),
+            Rule::Range(min, max) => match number() {
+                Some(n) if n >= *min && n <= *max => None,
+                _ => Some(format!("must be a number from {} to {}", min, max)),
+            },
+        }
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.rsplit_once('@') else {
+        return false;
+    };
+    let label_ok = |label: &str| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    !local.is_empty()
+        && local.len() <= 64
+        && !local.bytes().any(|b| b.is_ascii_whitespace() || b.is_ascii_control() || b == b'@')
+        && domain.contains('.')
+        && domain.split('.').all(label_ok)
+}
+
+// One failed rule
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    // Messages for one field, e.g. to render under its input
+    pub fn messages(&self, field: &str) -> Vec<&str> {
+        self.errors
+            .iter()
+            .filter(|e| e.field == field)
+            .map(|e| e.message.as_str())
+            .collect()
+    }
+
+    // 422 with {"errors": {"field": ["message", ...], ...}}
+    pub fn to_response(&self) -> Response {
+        let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+        for error in &self.errors {
+            match fields.iter_mut().find(|(field, _)| *field == error.field) {
+                Some((_, messages)) => messages.push(&error.message),
+                None => fields.push((&error.field, vec![&error.message])),
+            }
+        }
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(field, messages)| {
+                let messages: Vec<String> = messages.iter().map(|m| json_string(m)).collect();
+                format!("{}:[{}]", json_string(field), messages.join(","))
+            })
+            .collect();
+        let body = format!("{{\"errors\":{{{}}}}}", fields.join(","));
+        Response::new(422, body.into_bytes(), "application/json")
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self.errors.iter().map(|e| format!("{} {}", e.field, e.message)).collect();
+        f.write_str(&errors.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl IntoResponse for ValidationErrors {
+    fn into_response(self) -> Response {
+        self.to_response()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Clone, Default)]
+pub struct Validator {
+    fields: Vec<(String, Vec<Rule>)>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Rules for one field, checked in order. A field that is absent or empty
+    // only fails Required; the other rules apply to values that were given.
+    pub fn field(mut self, name: &str, rules: Vec<Rule>) -> Self {
+        self.fields.push((name.to_string(), rules));
+        self
+    }
+
+    // The declared fields present in `input`, or every rule that failed.
+    // Undeclared fields are dropped.
+    pub fn check(&self, input: &HashMap<String, String>) -> Result<HashMap<String, String>, ValidationErrors> {
+        let mut valid = HashMap::new();
+        let mut errors = Vec::new();
+        for (name, rules) in &self.fields {
+            let value = input.get(name).map(String::as_str).unwrap_or("");
+            for rule in rules {
+                if value.is_empty() && !matches!(rule, Rule::Required) {
+                    continue;
+                }
+                if let Some(message) = rule.check(value) {
+                    errors.push(FieldError {
+                        field: name.clone(),
+                        message,
+                    });
+                }
+            }
+            if let Some(value) = input.get(name) {
+                valid.insert(name.clone(), value.clone());
+            }
+        }
+        if errors.is_empty() { Ok(valid) } else { Err(ValidationErrors { errors }) }
+    }
+
+    // Same for a JSON object's top-level members: strings as they are,
+    // numbers and booleans as written, null as absent. Anything but an
+    // object fails every Required rule.
+    #[cfg(feature = "json")]
+    pub fn check_json(&self, input: &serde_json::Value) -> Result<HashMap<String, String>, ValidationErrors> {
+        let mut fields = HashMap::new();
+        if let Some(object) = input.as_object() {
+            for (name, value) in object {
+                let value = match value {
+                    serde_json::Value::Null => continue,
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                fields.insert(name.clone(), value);
+            }
+        }
+        self.check(&fields)
+    }
+}
@@ -0,0 +1,70 @@
+use crate::response::Response;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const ACME_PREFIX: &str = "acme-challenge/";
+
+// Handle for publishing ACME HTTP-01 key authorizations while the server runs.
+// Clones share the same token table, so an ACME client thread can hold one.
+#[derive(Clone, Default)]
+pub struct AcmeChallenges {
+    tokens: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl AcmeChallenges {
+    pub fn set(&self, token: &str, key_authorization: &str) {
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(token.to_string(), key_authorization.to_string());
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.tokens.lock().unwrap().remove(token);
+    }
+
+    pub fn clear(&self) {
+        self.tokens.lock().unwrap().clear();
+    }
+
+    fn get(&self, token: &str) -> Option<String> {
+        self.tokens.lock().unwrap().get(token).cloned()
+    }
+}
+
+// Documents served under /.well-known/ (RFC 8615)
+#[derive(Clone, Default)]
+pub(crate) struct WellKnown {
+    pub(crate) acme: AcmeChallenges,
+    documents: HashMap<String, (Vec<u8>, String)>,
+}
+
+impl WellKnown {
+    pub(crate) fn insert(&mut self, name: &str, body: Vec<u8>, content_type: &str) {
+        let name = name.trim_start_matches("/.well-known/").trim_matches('/');
+        self.documents
+            .insert(name.to_string(), (body, content_type.to_string()));
+    }
+
+    // `name` is the path below /.well-known/
+    pub(crate) fn respond(&self, name: &str) -> Option<Response> {
+        if let Some(token) = name.strip_prefix(ACME_PREFIX) {
+            if !is_acme_token(token) {
+                return None;
+            }
+            let key_auth = self.acme.get(token)?;
+            return Some(Response::new(200, key_auth.into_bytes(), "application/octet-stream"));
+        }
+        let (body, content_type) = self.documents.get(name)?;
+        Some(Response::new(200, body.clone(), content_type))
+    }
+}
+
+// Tokens are base64url without padding (RFC 8555 section 8.3)
+fn is_acme_token(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
@@ -0,0 +1,142 @@
+use crate::request::Request;
+use crate::response::Response;
+use crate::static_files::{serve_file, strip_mount};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type AuthorizeFn = Arc<dyn Fn(&Request) -> bool + Send + Sync>;
+
+// A directory that clients may upload into with PUT and remove from with
+// DELETE. GET serves its files like a static directory.
+#[derive(Clone)]
+pub struct WritableMount {
+    dir: PathBuf,
+    authorize: Option<AuthorizeFn>,
+    max_size: usize,
+}
+
+impl WritableMount {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            authorize: None,
+            max_size: 10 * 1024 * 1024,
+        }
+    }
+
+    // Decide whether a PUT/DELETE may go ahead. Without one every write is
+    // refused, so a mount is never accidentally world-writable.
+    pub fn authorize<F>(mut self, authorize: F) -> Self
+    where
+        F: Fn(&Request) -> bool + Send + Sync + 'static,
+    {
+        self.authorize = Some(Arc::new(authorize));
+        self
+    }
+
+    // Largest accepted upload in bytes
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    pub(crate) fn handle(&self, prefix: &str, req: &Request) -> Option<Response> {
+        let name = strip_mount(prefix, &req.path)?;
+        let writing = matches!(req.method.as_str(), "PUT" | "DELETE");
+        let Some(path) = safe_path(&self.dir, name) else {
+            return writing.then(|| text(400, "400 Bad Request: invalid file path"));
+        };
+        match req.method.as_str() {
+            "PUT" => Some(self.put(req, &path)),
+            "DELETE" => Some(self.delete(req, &path)),
+            _ => serve_file(&path, req),
+        }
+    }
+
+    fn put(&self, req: &Request, path: &Path) -> Response {
+        if !self.authorize.as_ref().is_some_and(|authorize| authorize(req)) {
+            return text(401, "401 Unauthorized");
+        }
+        let declared = req
+            .header("content-length")
+            .and_then(|len| len.trim().parse::<usize>().ok())
+            .unwrap_or(req.body.len());
+        if declared.max(req.body.len()) > self.max_size {
+            return text(413, "413 Content Too Large");
+        }
+        if path.is_dir() {
+            return text(409, "409 Conflict: path is a directory");
+        }
+        let existed = path.is_file();
+        if let Err(e) = write_atomic(path, &req.body) {
+            eprintln!("Failed to store {}: {}", path.display(), e);
+            return text(500, "500 Internal Server Error");
+        }
+        if existed {
+            Response::new(204, Vec::new(), "text/plain")
+        } else {
+            text(201, "201 Created").with_header("Location", &req.path)
+        }
+    }
+
+    fn delete(&self, req: &Request, path: &Path) -> Response {
+        if !self.authorize.as_ref().is_some_and(|authorize| authorize(req)) {
+            return text(401, "401 Unauthorized");
+        }
+        if !path.is_file() {
+            return text(404, "404 Not Found");
+        }
+        match fs::remove_file(path) {
+            Ok(()) => Response::new(204, Vec::new(), "text/plain"),
+            Err(e) => {
+                eprintln!("Failed to delete {}: {}", path.display(), e);
+                text(500, "500 Internal Server Error")
+            }
+        }
+    }
+}
+
+fn text(code: u16, body: &str) -> Response {
+    Response::new(code, body.as_bytes().to_vec(), "text/plain")
+}
+
+// Join `name` onto `dir`, refusing anything that could step outside it:
+// empty, `.` or `..` segments, backslashes, NULs and hidden files (which
+// also keeps our own temp files out of reach)
+fn safe_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.ends_with('/') {
+        return None;
+    }
+    let mut path = dir.to_path_buf();
+    for segment in name.split('/') {
+        if segment.is_empty()
+            || segment.starts_with('.')
+            || segment.contains(['\\', '\0', ':'])
+        {
+            return None;
+        }
+        path.push(segment);
+    }
+    Some(path)
+}
+
+// Write to a temp file next to `path` and rename it into place, so readers
+// never see a half-written upload
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = parent.join(format!(".{}.{}.tmp", file_name, nanos));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
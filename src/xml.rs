@@ -0,0 +1,69 @@
+// XML request bodies, behind the `xml` feature
+
+use crate::error::error_response;
+use crate::mime::Mime;
+use crate::request::Request;
+
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum XmlError {
+    // Content-Type was set to something other than XML
+    UnsupportedMediaType(String),
+    // The body isn't well-formed XML or doesn't fit the target type
+    Invalid(quick_xml::DeError),
+}
+
+impl XmlError {
+    // 415 when the body isn't declared as XML, 400 when it doesn't
+    // deserialize into the wanted type
+    pub fn status(&self) -> u16 {
+        match self {
+            XmlError::UnsupportedMediaType(_) => 415,
+            XmlError::Invalid(_) => 400,
+        }
+    }
+
+}
+
+error_response!(XmlError);
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlError::UnsupportedMediaType(content_type) => {
+                write!(f, "expected an XML body, got Content-Type {}", content_type)
+            }
+            XmlError::Invalid(e) => write!(f, "invalid XML body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for XmlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            XmlError::UnsupportedMediaType(_) => None,
+            XmlError::Invalid(e) => Some(e),
+        }
+    }
+}
+
+impl Request {
+    // Deserialize the body, e.g. let order: Order = req.xml()?. A missing
+    // Content-Type is accepted; any other than application/xml, text/xml or
+    // */*+xml is not. The body must be UTF-8.
+    pub fn xml<T: DeserializeOwned>(&self) -> Result<T, XmlError> {
+        if let Some(content_type) = self.header("content-type") {
+            let is_xml = Mime::parse(content_type).is_some_and(|mime| {
+                matches!(mime.essence(), "application/xml" | "text/xml") || mime.suffix() == Some("xml")
+            });
+            if !is_xml {
+                return Err(XmlError::UnsupportedMediaType(content_type.clone()));
+            }
+        }
+        let text = std::str::from_utf8(&self.body)
+            .map_err(|e| XmlError::Invalid(quick_xml::DeError::Custom(e.to_string())))?;
+        quick_xml::de::from_str(text).map_err(XmlError::Invalid)
+    }
+}